@@ -0,0 +1,28 @@
+#ifndef LIB_ATLAS_GLSL
+#define LIB_ATLAS_GLSL
+
+// Must match Tile::FLIP_X, Tile::FLIP_Y and Tile::ROTATE_90
+const uint FLIP_X = 1;
+const uint FLIP_Y = 2;
+const uint ROTATE_90 = 4;
+
+// Applies a tile's transform bits to `uv` within the tile, rotating before flipping.
+vec2 tile_uv(vec2 uv, uint transform) {
+  if ((transform & ROTATE_90) != 0) {
+    uv = vec2(uv.y, 1.0 - uv.x);
+  }
+  if ((transform & FLIP_X) != 0) {
+    uv.x = 1.0 - uv.x;
+  }
+  if ((transform & FLIP_Y) != 0) {
+    uv.y = 1.0 - uv.y;
+  }
+  return uv;
+}
+
+// Texture coordinates of `uv` within `tile` of an atlas `atlas_size` tiles wide and high.
+vec2 atlas_uv(uvec2 tile, vec2 uv, uvec2 atlas_size) {
+  return (uv + vec2(tile)) / vec2(atlas_size);
+}
+
+#endif
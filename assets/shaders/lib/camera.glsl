@@ -0,0 +1,18 @@
+#ifndef LIB_CAMERA_GLSL
+#define LIB_CAMERA_GLSL
+
+// Pixels from the top left corner of a viewport of `viewport_size` pixels to normalized device coordinates.
+vec2 pixel_to_ndc(vec2 pixel, vec2 viewport_size) {
+  return pixel / (viewport_size / 2.0) - 1.0;
+}
+
+vec2 ndc_to_pixel(vec2 ndc, vec2 viewport_size) {
+  return (ndc + 1.0) * (viewport_size / 2.0);
+}
+
+// A world position to clip space, with the view projection of a Camera2d.
+vec4 world_to_clip(mat4 view_projection, vec2 world) {
+  return view_projection * vec4(world, 0.0, 1.0);
+}
+
+#endif
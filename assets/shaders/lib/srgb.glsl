@@ -0,0 +1,23 @@
+#ifndef LIB_SRGB_GLSL
+#define LIB_SRGB_GLSL
+
+// Exact sRGB transfer functions, for colors that reach an sRGB attachment without going through an sRGB sampler.
+vec3 srgb_to_linear(vec3 srgb) {
+  bvec3 low = lessThanEqual(srgb, vec3(0.04045));
+  return mix(pow((srgb + 0.055) / 1.055, vec3(2.4)), srgb / 12.92, low);
+}
+
+vec3 linear_to_srgb(vec3 linear) {
+  bvec3 low = lessThanEqual(linear, vec3(0.0031308));
+  return mix(1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, linear * 12.92, low);
+}
+
+vec4 srgb_to_linear(vec4 srgb) {
+  return vec4(srgb_to_linear(srgb.rgb), srgb.a);
+}
+
+vec4 linear_to_srgb(vec4 linear) {
+  return vec4(linear_to_srgb(linear.rgb), linear.a);
+}
+
+#endif
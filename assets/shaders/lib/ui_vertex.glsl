@@ -0,0 +1,27 @@
+#ifndef LIB_UI_VERTEX_GLSL
+#define LIB_UI_VERTEX_GLSL
+
+// The whole vertex stage of the UI pipelines, drawing imgui style vertices given in window pixels.
+
+#include <camera.glsl>
+
+layout(push_constant) uniform PushConstants {
+  float window_width;
+  float window_height;
+} push_constants;
+
+layout(location = 0) in vec2 pos;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 col;
+
+layout(location = 0) out vec2 uv_out;
+layout(location = 1) out vec4 col_out;
+
+void main() {
+  uv_out = uv;
+  col_out = col;
+  vec2 window_size = vec2(push_constants.window_width, push_constants.window_height);
+  gl_Position = vec4(pixel_to_ndc(pos, window_size), 0.0, 1.0);
+}
+
+#endif
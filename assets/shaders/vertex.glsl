@@ -1,15 +1,18 @@
 #version 450
 
+#include <atlas.glsl>
+#include <camera.glsl>
+
 layout(location = 0) in vec2 in_coord;
 layout(location = 1) in uvec2 tile;
+layout(location = 2) in uint transform;
 
-//layout(constant_id = 0) const uint tile_size_x = 10;
-//layout(constant_id = 1) const uint tile_size_y = 10;
-//
 layout(push_constant) uniform Constants {
-  vec2 offset;
-  uint tile_size_x;
-  uint tile_size_y;
+  mat4 view_projection;
+  // Tiles in the atlas horizontally and vertically
+  uvec2 atlas_size;
+  // Size of a tile in world units
+  float tile_size;
 } push;
 
 layout(location = 0) out vec2 tex_coords;
@@ -17,8 +20,7 @@ layout(location = 0) out vec2 tex_coords;
 void main() {
   float x = gl_VertexIndex % 2;
   float y = gl_VertexIndex / 2;
-  gl_Position = vec4(in_coord.x + x * 0.1, in_coord.y + y * 0.1, 0.0, 1.0) + vec4(push.offset, 0.0, 0.0);
-  uint tile_x = tile.x;
-  uint tile_y = tile.y;
-  tex_coords = vec2((x + tile_x) / push.tile_size_x, (y + tile_y) / push.tile_size_y);
-}
\ No newline at end of file
+  gl_Position = world_to_clip(push.view_projection, in_coord + vec2(x, y) * push.tile_size);
+  vec2 uv = tile_uv(vec2(x, y), transform);
+  tex_coords = atlas_uv(tile, uv, push.atlas_size);
+}
@@ -1,5 +1,5 @@
 use bevy_app::prelude::*;
-use crate::editor::ui::{inspector_ui, main_menu};
+use crate::editor::ui::{game_window, inspector_ui, log_window, main_menu, shader_errors_window};
 
 pub mod ui;
 
@@ -8,6 +8,9 @@ pub struct RuminativeEditorPlugin;
 impl Plugin for RuminativeEditorPlugin {
   fn build(&self, app: &mut App) {
     app.add_systems(Update, inspector_ui);
+    app.add_systems(Update, game_window);
+    app.add_systems(Update, log_window);
+    app.add_systems(Update, shader_errors_window);
     app.add_systems(Update, main_menu);
   }
 }
\ No newline at end of file
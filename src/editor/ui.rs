@@ -1,5 +1,10 @@
 use bevy_ecs::prelude::*;
-use imgui::{Context};
+use imgui::{Context, Image, StyleVar};
+use crate::engine::GameViewport;
+use crate::engine::device::SelectedDevice;
+use crate::engine::log::{EngineLog, LogLevel};
+use crate::engine::imgui_pipeline::{ImguiStats, GAME_TEXTURE_ID};
+use crate::engine::shader_reload::ShaderReloader;
 
 pub fn inspector_ui(
   mut imgui: NonSendMut<Context>,
@@ -10,12 +15,90 @@ pub fn inspector_ui(
     });
 }
 
+pub fn game_window(
+  mut imgui: NonSendMut<Context>,
+  mut game_viewport: ResMut<GameViewport>,
+) {
+  let ui = imgui.current_frame();
+  let padding = ui.push_style_var(StyleVar::WindowPadding([0.0, 0.0]));
+  ui.window("Game")
+    .build(|| {
+      let size = ui.content_region_avail();
+      game_viewport.pos = ui.cursor_screen_pos();
+      game_viewport.size = size;
+      Image::new(GAME_TEXTURE_ID, size).build(&ui);
+    });
+  padding.pop();
+}
+
+pub fn log_window(
+  mut imgui: NonSendMut<Context>,
+  log: Res<EngineLog>,
+) {
+  let ui = imgui.current_frame();
+  ui.window("Log")
+    .build(|| {
+      ui.text(format!(
+        "{} errors ({} validation), {} warnings",
+        log.errors(),
+        log.validation_errors(),
+        log.warnings()
+      ));
+      ui.same_line();
+      if ui.button("Clear") {
+        log.clear();
+      }
+      ui.separator();
+      ui.child_window("entries").build(|| {
+        for entry in log.entries() {
+          let color = match entry.level {
+            LogLevel::Error => [1.0, 0.4, 0.4, 1.0],
+            LogLevel::Warning => [1.0, 0.8, 0.3, 1.0],
+            LogLevel::Info | LogLevel::Verbose => [0.8, 0.8, 0.8, 1.0],
+          };
+          ui.text_colored(color, entry.to_string());
+        }
+        // Follow new entries unless scrolled up to read older ones
+        if ui.scroll_y() >= ui.scroll_max_y() {
+          ui.set_scroll_here_y_with_ratio(1.0);
+        }
+      });
+    });
+}
+
+/// Only shown while a reloaded shader fails to compile, the previous version keeps running meanwhile.
+pub fn shader_errors_window(
+  mut imgui: NonSendMut<Context>,
+  reloader: Res<ShaderReloader>,
+) {
+  if reloader.errors().next().is_none() {
+    return;
+  }
+  let ui = imgui.current_frame();
+  ui.window("Shader errors")
+    .build(|| {
+      for (name, error) in reloader.errors() {
+        ui.text_colored([1.0, 0.4, 0.4, 1.0], name);
+        ui.text_wrapped(error);
+        ui.separator();
+      }
+    });
+}
+
 pub fn main_menu(
   mut imgui: NonSendMut<Context>,
+  imgui_stats: Res<ImguiStats>,
+  selected_device: Res<SelectedDevice>,
 ) {
   let ui = imgui.current_frame();
   ui.main_menu_bar(|| {
     if ui.button("SAVE") {
     }
+    ui.text(format!(
+      "imgui: {:.1} KiB uploaded / {} KiB arena",
+      imgui_stats.uploaded_bytes as f32 / 1024.0,
+      imgui_stats.arena_size / 1024
+    ));
+    ui.text(format!("{} ({:?})", selected_device.name(), selected_device.device_type()));
   });
-}
\ No newline at end of file
+}
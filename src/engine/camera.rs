@@ -0,0 +1,349 @@
+use crate::engine::GameViewport;
+use bevy_ecs::prelude::*;
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+/// Part of the game view a [`Camera2d`] renders to, as fractions of its size, e.g. the left half for split screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraViewport {
+  pub offset: [f32; 2],
+  pub size: [f32; 2],
+}
+
+impl Default for CameraViewport {
+  fn default() -> Self {
+    Self {
+      offset: [0.0, 0.0],
+      size: [1.0, 1.0],
+    }
+  }
+}
+
+/// Looks at the game world, which is measured in atlas pixels with y pointing down like on screen. Game passes draw
+/// once per camera into its [`CameraViewport`] of the [`GameTarget`](crate::engine::game_target::GameTarget), with
+/// [`view_projection`](Self::view_projection) as push constant. Without any camera they use the default one,
+/// centered on the origin at zoom 1.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Camera2d {
+  /// World position shown at the center of the viewport.
+  pub position: [f32; 2],
+  /// Physical pixels per world unit.
+  pub zoom: f32,
+  /// Radians the world is turned counter-clockwise on screen.
+  pub rotation: f32,
+  pub viewport: CameraViewport,
+  /// Rounds the zoom above 1 and the view position to whole pixels while the camera isn't rotated, so pixel art
+  /// tiles keep the same size and sharp edges as the camera moves.
+  pub pixel_snap: bool,
+}
+
+impl Default for Camera2d {
+  fn default() -> Self {
+    Self {
+      position: [0.0, 0.0],
+      zoom: 1.0,
+      rotation: 0.0,
+      viewport: CameraViewport::default(),
+      pixel_snap: true,
+    }
+  }
+}
+
+impl Camera2d {
+  /// Moves the view by `delta` physical pixels, e.g. the mouse drag since the last frame.
+  pub fn pan(&mut self, delta: [f32; 2]) {
+    let zoom = self.effective_zoom();
+    let [x, y] = Self::rotate([delta[0] / zoom, delta[1] / zoom], self.rotation);
+    self.position = [self.position[0] - x, self.position[1] - y];
+  }
+
+  /// Multiplies the zoom by `factor`, keeping the world point `anchor` at the same place on screen, give or take
+  /// the pixel snapping. Factors that aren't positive are ignored.
+  pub fn zoom_around(&mut self, anchor: [f32; 2], factor: f32) {
+    if factor <= 0.0 || factor.is_nan() {
+      return;
+    }
+    let before = self.effective_zoom();
+    self.zoom *= factor;
+    let ratio = before / self.effective_zoom();
+    self.position = [
+      anchor[0] + (self.position[0] - anchor[0]) * ratio,
+      anchor[1] + (self.position[1] - anchor[1]) * ratio,
+    ];
+  }
+
+  /// The region of a target of `target_extent` pixels this camera renders to, on whole pixels.
+  pub fn viewport(&self, target_extent: [u32; 2]) -> Viewport {
+    let (offset, extent) = self.region(target_extent);
+    Viewport {
+      offset,
+      extent,
+      ..Default::default()
+    }
+  }
+
+  /// Column-major matrix taking world positions to clip space for a target of `target_extent` pixels, for
+  /// `world_to_clip` in `camera.glsl`.
+  pub fn view_projection(&self, target_extent: [u32; 2]) -> [[f32; 4]; 4] {
+    let (_, extent) = self.region(target_extent);
+    let ([[m00, m01], [m10, m11]], [tx, ty]) = self.transform(extent);
+    let [sx, sy] = [2.0 / extent[0], 2.0 / extent[1]];
+    [
+      [m00 * sx, m10 * sy, 0.0, 0.0],
+      [m01 * sx, m11 * sy, 0.0, 0.0],
+      [0.0, 0.0, 1.0, 0.0],
+      [tx * sx - 1.0, ty * sy - 1.0, 0.0, 1.0],
+    ]
+  }
+
+  /// Where `world` shows up in the window, in the logical coordinates of [`GameViewport`] and imgui.
+  /// `target_extent` is the game target's size in physical pixels.
+  pub fn world_to_screen(
+    &self,
+    world: [f32; 2],
+    game_viewport: &GameViewport,
+    target_extent: [u32; 2],
+  ) -> [f32; 2] {
+    let (offset, extent) = self.region(target_extent);
+    let ([[m00, m01], [m10, m11]], [tx, ty]) = self.transform(extent);
+    let pixel = [
+      offset[0] + m00 * world[0] + m01 * world[1] + tx,
+      offset[1] + m10 * world[0] + m11 * world[1] + ty,
+    ];
+    let scale = Self::logical_scale(game_viewport, target_extent);
+    [
+      game_viewport.pos[0] + pixel[0] * scale[0],
+      game_viewport.pos[1] + pixel[1] * scale[1],
+    ]
+  }
+
+  /// The world position under `screen`, e.g. the mouse cursor, given in [`GameViewport`]'s logical coordinates.
+  pub fn screen_to_world(
+    &self,
+    screen: [f32; 2],
+    game_viewport: &GameViewport,
+    target_extent: [u32; 2],
+  ) -> [f32; 2] {
+    let (offset, extent) = self.region(target_extent);
+    let (_, [tx, ty]) = self.transform(extent);
+    let scale = Self::logical_scale(game_viewport, target_extent);
+    let pixel = [
+      (screen[0] - game_viewport.pos[0]) / scale[0] - offset[0] - tx,
+      (screen[1] - game_viewport.pos[1]) / scale[1] - offset[1] - ty,
+    ];
+    let zoom = self.effective_zoom();
+    Self::rotate([pixel[0] / zoom, pixel[1] / zoom], self.rotation)
+  }
+
+  fn snaps(&self) -> bool {
+    self.pixel_snap && self.rotation == 0.0
+  }
+
+  /// Whole numbers of pixels per world unit from 1 up keep every tile the same size on screen.
+  fn effective_zoom(&self) -> f32 {
+    if self.snaps() && self.zoom >= 1.0 {
+      self.zoom.round()
+    } else {
+      self.zoom
+    }
+  }
+
+  /// `v` turned by `angle`, which is clockwise on screen since y points down.
+  fn rotate(v: [f32; 2], angle: f32) -> [f32; 2] {
+    let (sin, cos) = angle.sin_cos();
+    [cos * v[0] - sin * v[1], sin * v[0] + cos * v[1]]
+  }
+
+  /// Row-major linear part and translation taking world positions to pixels within a region of `extent` pixels.
+  fn transform(&self, extent: [f32; 2]) -> ([[f32; 2]; 2], [f32; 2]) {
+    let zoom = self.effective_zoom();
+    let (sin, cos) = self.rotation.sin_cos();
+    let linear = [[cos * zoom, sin * zoom], [-sin * zoom, cos * zoom]];
+    let [x, y] = self.position;
+    let mut translation = [
+      extent[0] / 2.0 - (linear[0][0] * x + linear[0][1] * y),
+      extent[1] / 2.0 - (linear[1][0] * x + linear[1][1] * y),
+    ];
+    if self.snaps() {
+      translation = [translation[0].round(), translation[1].round()];
+    }
+    (linear, translation)
+  }
+
+  /// Offset and extent in pixels of the viewport within a target of `target_extent` pixels.
+  fn region(&self, target_extent: [u32; 2]) -> ([f32; 2], [f32; 2]) {
+    let [width, height] = [target_extent[0] as f32, target_extent[1] as f32];
+    let offset = [
+      (self.viewport.offset[0] * width).round(),
+      (self.viewport.offset[1] * height).round(),
+    ];
+    let extent = [
+      (self.viewport.size[0] * width).round().max(1.0),
+      (self.viewport.size[1] * height).round().max(1.0),
+    ];
+    (offset, extent)
+  }
+
+  /// Logical units per physical pixel of the game target.
+  fn logical_scale(game_viewport: &GameViewport, target_extent: [u32; 2]) -> [f32; 2] {
+    [
+      game_viewport.size[0] / target_extent[0].max(1) as f32,
+      game_viewport.size[1] / target_extent[1].max(1) as f32,
+    ]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TARGET: [u32; 2] = [1280, 720];
+
+  /// The game view showing the target at half its size, 100 by 40 logical units into the window.
+  fn game_viewport() -> GameViewport {
+    GameViewport {
+      pos: [100.0, 40.0],
+      size: [640.0, 360.0],
+    }
+  }
+
+  /// A game view as big as the target, so screen and pixel coordinates are the same.
+  fn pixel_viewport() -> GameViewport {
+    GameViewport {
+      pos: [0.0, 0.0],
+      size: [TARGET[0] as f32, TARGET[1] as f32],
+    }
+  }
+
+  fn assert_near(a: [f32; 2], b: [f32; 2], tolerance: f32) {
+    assert!(
+      (a[0] - b[0]).abs() <= tolerance && (a[1] - b[1]).abs() <= tolerance,
+      "{a:?} is not within {tolerance} of {b:?}"
+    );
+  }
+
+  fn cameras() -> Vec<Camera2d> {
+    let mut cameras = vec![];
+    for zoom in [0.37, 1.0, 2.6] {
+      for rotation in [0.0, 0.3, -2.0] {
+        for pixel_snap in [false, true] {
+          cameras.push(Camera2d {
+            position: [13.7, -8.25],
+            zoom,
+            rotation,
+            viewport: CameraViewport {
+              offset: [0.5, 0.25],
+              size: [0.5, 0.6],
+            },
+            pixel_snap,
+          });
+        }
+      }
+    }
+    cameras
+  }
+
+  #[test]
+  fn screen_to_world_inverts_world_to_screen() {
+    let game_viewport = game_viewport();
+    for camera in cameras() {
+      for world in [[0.0, 0.0], [13.7, -8.25], [-120.5, 64.0], [300.0, 301.25]] {
+        let screen = camera.world_to_screen(world, &game_viewport, TARGET);
+        assert_near(camera.screen_to_world(screen, &game_viewport, TARGET), world, 1e-3);
+      }
+    }
+  }
+
+  #[test]
+  fn view_projection_matches_world_to_screen() {
+    let game_viewport = pixel_viewport();
+    for camera in cameras() {
+      let m = camera.view_projection(TARGET);
+      let viewport = camera.viewport(TARGET);
+      for world in [[0.0, 0.0], [-120.5, 64.0], [300.0, 301.25]] {
+        let clip = [
+          m[0][0] * world[0] + m[1][0] * world[1] + m[3][0],
+          m[0][1] * world[0] + m[1][1] * world[1] + m[3][1],
+        ];
+        let pixel = [
+          viewport.offset[0] + (clip[0] + 1.0) / 2.0 * viewport.extent[0],
+          viewport.offset[1] + (clip[1] + 1.0) / 2.0 * viewport.extent[1],
+        ];
+        assert_near(pixel, camera.world_to_screen(world, &game_viewport, TARGET), 1e-2);
+      }
+    }
+  }
+
+  #[test]
+  fn centers_the_position_in_its_viewport() {
+    let camera = Camera2d {
+      position: [40.0, 24.0],
+      zoom: 3.0,
+      viewport: CameraViewport {
+        offset: [0.5, 0.0],
+        size: [0.5, 1.0],
+      },
+      ..Default::default()
+    };
+    assert_near(camera.world_to_screen([40.0, 24.0], &pixel_viewport(), TARGET), [960.0, 360.0], 1e-3);
+    assert_near(camera.world_to_screen([40.0, 24.0], &game_viewport(), TARGET), [580.0, 220.0], 1e-3);
+    assert_near(camera.world_to_screen([41.0, 24.0], &pixel_viewport(), TARGET), [963.0, 360.0], 1e-3);
+  }
+
+  #[test]
+  fn snapped_tile_corners_land_on_whole_pixels() {
+    let game_viewport = pixel_viewport();
+    for zoom in [0.5, 1.0, 1.4, 2.6, 3.0] {
+      for position in [[0.0, 0.0], [10.37, -4.81], [-333.3, 77.7]] {
+        let camera = Camera2d {
+          position,
+          zoom,
+          viewport: CameraViewport {
+            offset: [0.25, 0.1],
+            size: [0.5, 0.75],
+          },
+          ..Default::default()
+        };
+        for corner in [[0.0, 0.0], [16.0, 0.0], [32.0, -48.0], [-160.0, 96.0]] {
+          let pixel = camera.world_to_screen(corner, &game_viewport, TARGET);
+          assert_near(pixel, [pixel[0].round(), pixel[1].round()], 1e-3);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn zoom_around_keeps_the_anchor_in_place() {
+    let game_viewport = pixel_viewport();
+    for pixel_snap in [false, true] {
+      for factor in [0.5, 1.25, 2.0, 3.7] {
+        let mut camera = Camera2d {
+          position: [13.7, -8.25],
+          zoom: 1.6,
+          pixel_snap,
+          ..Default::default()
+        };
+        let anchor = [40.0, 12.5];
+        let before = camera.world_to_screen(anchor, &game_viewport, TARGET);
+        camera.zoom_around(anchor, factor);
+        assert!((camera.zoom - 1.6 * factor).abs() < 1e-5);
+        // Snapping moves the view by up to half a pixel, before and after
+        let tolerance = if pixel_snap { 1.0 + 1e-3 } else { 1e-3 };
+        assert_near(camera.world_to_screen(anchor, &game_viewport, TARGET), before, tolerance);
+      }
+    }
+  }
+
+  #[test]
+  fn zoom_around_ignores_factors_that_are_not_positive() {
+    for factor in [0.0, -1.0, f32::NAN] {
+      let mut camera = Camera2d {
+        position: [13.7, -8.25],
+        zoom: 1.6,
+        ..Default::default()
+      };
+      camera.zoom_around([40.0, 12.5], factor);
+      assert_eq!(camera.zoom, 1.6);
+      assert_eq!(camera.position, [13.7, -8.25]);
+    }
+  }
+}
@@ -0,0 +1,340 @@
+use crate::engine::Resultat;
+use bevy_ecs::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
+use vulkano::device::{Device, DeviceExtensions, Features, Properties, QueueFlags};
+use vulkano::instance::Instance;
+use vulkano::swapchain::Surface;
+
+/// Overrides [`EngineSettings::device`](crate::engine::settings::EngineSettings::device), parsed with
+/// [`DeviceSelector::parse`]. `RUMINATIVE_DEVICE=cpu` forces lavapipe or another software driver, e.g. in CI.
+pub const DEVICE_ENV_VAR: &str = "RUMINATIVE_DEVICE";
+
+/// Mirrors [`PhysicalDeviceType`], so it can be written in settings files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceType {
+  Discrete,
+  Integrated,
+  Virtual,
+  Cpu,
+  Other,
+}
+
+impl From<PhysicalDeviceType> for DeviceType {
+  fn from(device_type: PhysicalDeviceType) -> Self {
+    match device_type {
+      PhysicalDeviceType::DiscreteGpu => DeviceType::Discrete,
+      PhysicalDeviceType::IntegratedGpu => DeviceType::Integrated,
+      PhysicalDeviceType::VirtualGpu => DeviceType::Virtual,
+      PhysicalDeviceType::Cpu => DeviceType::Cpu,
+      _ => DeviceType::Other,
+    }
+  }
+}
+
+/// Forces a particular GPU instead of the best ranked one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceSelector {
+  /// Part of the device name, ignoring case, e.g. `"llvmpipe"`.
+  Name(String),
+  /// Position in the order the driver lists devices, as shown by [`candidates`].
+  Index(usize),
+  Type(DeviceType),
+}
+
+impl DeviceSelector {
+  /// A number selects by index, `discrete`, `integrated`, `virtual`, `cpu` or `other` by type, and anything else
+  /// by name.
+  pub fn parse(selector: &str) -> Self {
+    let selector = selector.trim();
+    if let Ok(index) = selector.parse() {
+      return DeviceSelector::Index(index);
+    }
+    match selector.to_lowercase().as_str() {
+      "discrete" => DeviceSelector::Type(DeviceType::Discrete),
+      "integrated" => DeviceSelector::Type(DeviceType::Integrated),
+      "virtual" => DeviceSelector::Type(DeviceType::Virtual),
+      "cpu" => DeviceSelector::Type(DeviceType::Cpu),
+      "other" => DeviceSelector::Type(DeviceType::Other),
+      _ => DeviceSelector::Name(selector.to_string()),
+    }
+  }
+
+  /// The selector in [`DEVICE_ENV_VAR`], if it is set and not empty.
+  pub fn from_env() -> Option<Self> {
+    env::var(DEVICE_ENV_VAR).ok().and_then(|selector| Self::parse_non_empty(&selector))
+  }
+
+  /// [`DeviceSelector::parse`], or `None` if `selector` is empty or only whitespace.
+  fn parse_non_empty(selector: &str) -> Option<Self> {
+    (!selector.trim().is_empty()).then(|| Self::parse(selector))
+  }
+
+  pub fn matches(&self, index: usize, physical_device: &PhysicalDevice) -> bool {
+    let properties = physical_device.properties();
+    match self {
+      DeviceSelector::Name(name) => properties.device_name.to_lowercase().contains(&name.to_lowercase()),
+      DeviceSelector::Index(i) => *i == index,
+      DeviceSelector::Type(device_type) => DeviceType::from(properties.device_type) == *device_type,
+    }
+  }
+}
+
+/// Device extensions and features render plugins need. Plugins add theirs while they are built, the engine merges
+/// them and creates the device in `Plugin::finish`, on a device that supports every required one. Optional ones are
+/// enabled where supported, check [`EnabledOptionals`] to see which made it and fall back otherwise.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct DeviceRequirements {
+  pub required_extensions: DeviceExtensions,
+  pub optional_extensions: DeviceExtensions,
+  pub required_features: Features,
+  pub optional_features: Features,
+}
+
+impl DeviceRequirements {
+  pub fn require_extensions(&mut self, extensions: DeviceExtensions) -> &mut Self {
+    self.required_extensions = self.required_extensions.union(&extensions);
+    self
+  }
+
+  pub fn request_extensions(&mut self, extensions: DeviceExtensions) -> &mut Self {
+    self.optional_extensions = self.optional_extensions.union(&extensions);
+    self
+  }
+
+  pub fn require_features(&mut self, features: Features) -> &mut Self {
+    self.required_features = self.required_features.union(&features);
+    self
+  }
+
+  pub fn request_features(&mut self, features: Features) -> &mut Self {
+    self.optional_features = self.optional_features.union(&features);
+    self
+  }
+
+  /// What to enable on `physical_device`: everything required plus the optional ones it supports.
+  pub fn enabled_on(&self, physical_device: &PhysicalDevice) -> (DeviceExtensions, Features) {
+    let extensions = self.optional_extensions.intersection(physical_device.supported_extensions());
+    let features = self.optional_features.intersection(physical_device.supported_features());
+    (
+      self.required_extensions.union(&extensions),
+      self.required_features.union(&features),
+    )
+  }
+}
+
+/// The optional extensions and features from [`DeviceRequirements`] that are enabled on the current device.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct EnabledOptionals {
+  pub extensions: DeviceExtensions,
+  pub features: Features,
+}
+
+impl EnabledOptionals {
+  pub fn new(requirements: &DeviceRequirements, device: &Device) -> Self {
+    Self {
+      extensions: requirements.optional_extensions.intersection(device.enabled_extensions()),
+      features: requirements.optional_features.intersection(device.enabled_features()),
+    }
+  }
+}
+
+/// Why a [`DeviceCandidate`] wasn't picked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rejection {
+  MissingExtensions(DeviceExtensions),
+  MissingFeatures(Features),
+  NoGraphicsQueue,
+  /// None of its graphics queues can present to the window's surface.
+  NoPresentSupport,
+  /// Usable, but the [`DeviceSelector`] asked for another device.
+  NotSelected,
+  /// Usable, but a device of a better type was picked.
+  Outranked,
+}
+
+impl fmt::Display for Rejection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Rejection::MissingExtensions(extensions) => write!(f, "missing extensions {extensions:?}"),
+      Rejection::MissingFeatures(features) => write!(f, "missing features {features:?}"),
+      Rejection::NoGraphicsQueue => write!(f, "no graphics queue"),
+      Rejection::NoPresentSupport => write!(f, "can't present to the window"),
+      Rejection::NotSelected => write!(f, "not the selected device"),
+      Rejection::Outranked => write!(f, "a better device is available"),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceCandidate {
+  pub index: usize,
+  pub physical_device: Arc<PhysicalDevice>,
+  /// The graphics queue family the engine would use, if there is one.
+  pub queue_family_index: Option<u32>,
+  /// `None` for the device that was picked.
+  pub rejection: Option<Rejection>,
+}
+
+impl DeviceCandidate {
+  pub fn name(&self) -> &str {
+    &self.physical_device.properties().device_name
+  }
+
+  pub fn device_type(&self) -> PhysicalDeviceType {
+    self.physical_device.properties().device_type
+  }
+}
+
+impl fmt::Display for DeviceCandidate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {} ({:?})", self.index, self.name(), self.device_type())?;
+    match &self.rejection {
+      Some(rejection) => write!(f, ", {rejection}"),
+      None => write!(f, ", selected"),
+    }
+  }
+}
+
+/// Lower is better, used when nothing is forced or several devices match the selector.
+fn rank(device_type: PhysicalDeviceType) -> u32 {
+  match device_type {
+    PhysicalDeviceType::DiscreteGpu => 0,
+    PhysicalDeviceType::IntegratedGpu => 1,
+    PhysicalDeviceType::VirtualGpu => 2,
+    PhysicalDeviceType::Cpu => 3,
+    PhysicalDeviceType::Other => 4,
+    _ => 5,
+  }
+}
+
+/// Every physical device of `instance`, with the reason it can't or won't be used. At most one candidate, the
+/// best ranked device that meets the required `requirements`, can draw to `surface` and matches `selector`, has no
+/// rejection.
+pub fn candidates(
+  instance: &Arc<Instance>,
+  surface: Option<&Surface>,
+  requirements: &DeviceRequirements,
+  selector: Option<&DeviceSelector>,
+) -> Resultat<Vec<DeviceCandidate>> {
+  let mut candidates = instance
+    .enumerate_physical_devices()?
+    .enumerate()
+    .map(|(index, physical_device)| {
+      let missing_extensions = requirements.required_extensions.difference(physical_device.supported_extensions());
+      let missing_features = requirements.required_features.difference(physical_device.supported_features());
+      let graphics_families = physical_device
+        .queue_family_properties()
+        .iter()
+        .enumerate()
+        .filter(|(_, q)| q.queue_flags.intersects(QueueFlags::GRAPHICS))
+        .map(|(i, _)| i as u32)
+        .collect::<Vec<_>>();
+      let queue_family_index = graphics_families.iter().copied().find(|&i| {
+        surface.map_or(true, |surface| physical_device.surface_support(i, surface).unwrap_or(false))
+      });
+      let rejection = if !missing_extensions.is_empty() {
+        Some(Rejection::MissingExtensions(missing_extensions))
+      } else if !missing_features.is_empty() {
+        Some(Rejection::MissingFeatures(missing_features))
+      } else if graphics_families.is_empty() {
+        Some(Rejection::NoGraphicsQueue)
+      } else if queue_family_index.is_none() {
+        Some(Rejection::NoPresentSupport)
+      } else if selector.is_some_and(|selector| !selector.matches(index, &physical_device)) {
+        Some(Rejection::NotSelected)
+      } else {
+        None
+      };
+      DeviceCandidate {
+        index,
+        physical_device,
+        queue_family_index,
+        rejection,
+      }
+    })
+    .collect::<Vec<_>>();
+
+  let chosen = candidates
+    .iter()
+    .filter(|candidate| candidate.rejection.is_none())
+    .min_by_key(|candidate| rank(candidate.device_type()))
+    .map(|candidate| candidate.index);
+  for candidate in candidates.iter_mut() {
+    if candidate.rejection.is_none() && Some(candidate.index) != chosen {
+      candidate.rejection = Some(Rejection::Outranked);
+    }
+  }
+  Ok(candidates)
+}
+
+/// The physical device the engine runs on. Its limits are part of the properties, e.g.
+/// `properties().max_image_dimension2_d`.
+#[derive(Resource, Clone, Debug)]
+pub struct SelectedDevice {
+  pub index: usize,
+  pub physical_device: Arc<PhysicalDevice>,
+  pub queue_family_index: u32,
+}
+
+impl SelectedDevice {
+  pub fn name(&self) -> &str {
+    &self.properties().device_name
+  }
+
+  pub fn device_type(&self) -> PhysicalDeviceType {
+    self.properties().device_type
+  }
+
+  pub fn properties(&self) -> &Properties {
+    self.physical_device.properties()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_indices() {
+    assert_eq!(DeviceSelector::parse("0"), DeviceSelector::Index(0));
+    assert_eq!(DeviceSelector::parse("12"), DeviceSelector::Index(12));
+    assert_eq!(DeviceSelector::parse(" 3\n"), DeviceSelector::Index(3));
+  }
+
+  #[test]
+  fn parses_types_in_any_case() {
+    let types = [
+      ("discrete", DeviceType::Discrete),
+      ("integrated", DeviceType::Integrated),
+      ("virtual", DeviceType::Virtual),
+      ("cpu", DeviceType::Cpu),
+      ("other", DeviceType::Other),
+    ];
+    for (keyword, device_type) in types {
+      for selector in [keyword.to_string(), keyword.to_uppercase(), keyword[..1].to_uppercase() + &keyword[1..]] {
+        assert_eq!(DeviceSelector::parse(&selector), DeviceSelector::Type(device_type), "{selector}");
+      }
+    }
+  }
+
+  #[test]
+  fn parses_trimmed_names() {
+    assert_eq!(
+      DeviceSelector::parse("  llvmpipe (LLVM 15.0.7, 256 bits)\t"),
+      DeviceSelector::Name("llvmpipe (LLVM 15.0.7, 256 bits)".into())
+    );
+    assert_eq!(DeviceSelector::parse(" GeForce "), DeviceSelector::Name("GeForce".into()));
+    assert_eq!(DeviceSelector::parse("-1"), DeviceSelector::Name("-1".into()));
+  }
+
+  #[test]
+  fn ignores_empty_selectors() {
+    assert_eq!(DeviceSelector::parse_non_empty(""), None);
+    assert_eq!(DeviceSelector::parse_non_empty(" \t\n"), None);
+    assert_eq!(DeviceSelector::parse_non_empty(" cpu "), Some(DeviceSelector::Type(DeviceType::Cpu)));
+  }
+}
@@ -1,19 +1,38 @@
+use crate::engine::device::DeviceRequirements;
+use crate::engine::error::{EngineError, EngineErrorEvent};
+use crate::engine::frames::FrameInFlight;
+use crate::engine::game_target::GameTargetPlugin;
 use crate::engine::imgui_pipeline::ImguiPipeline;
 use crate::engine::internals::RuminativeInternals;
+use crate::engine::log::{EngineLog, LogLevel, LogSource};
+use crate::engine::pipeline_cache;
+use crate::engine::render_graph::RenderGraph;
 use crate::engine::rumigui_pipeline::RumiguiPipeline;
-use crate::engine::{ANamedSingleton, ASingleton, GameViewport, KeyPressed, PipelineRunner, Singleton, WinitEvent};
-use bevy_app::{App, Plugin};
+use crate::engine::settings::EngineSettings;
+use crate::engine::shader_reload::{ShaderReloadPlugin, ShaderReloader};
+use crate::engine::{
+  ANamedSingleton, ASingleton, DeviceRecreated, GameViewport, KeyPressed, NamedSingleton, Resultat, Singleton,
+  SwapchainRecreated, WinitEvent,
+};
+use bevy_app::{App, AppExit, Plugin};
+use bevy_ecs::event::{Events, ManualEventReader};
+use serde_derive::{Deserialize, Serialize};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use imgui::{Context};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::command_buffer::{
   AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer
 };
-use vulkano::device::{Device, Queue};
+use vulkano::device::{Device, DeviceExtensions, DeviceOwned, Features, Queue};
+use vulkano::format::Format;
 use vulkano::image::view::{ImageView};
 use vulkano::image::Image;
+use vulkano::pipeline::cache::PipelineCache;
 use vulkano::pipeline::graphics::viewport::Viewport;
 use vulkano::swapchain::{acquire_next_image, Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo};
+use vulkano::sync::future::FenceSignalFuture;
 use vulkano::sync::GpuFuture;
 use vulkano::{sync, Validated, VulkanError};
 use winit::event::{Event, WindowEvent};
@@ -30,171 +49,634 @@ fn window_size_dependent_setup(images: &[Arc<Image>], viewport: &mut Viewport) -
     .collect::<Vec<_>>()
 }
 
-pub struct RuminativeEnginePlugin;
+/// Signalled when the GPU is done with a frame in flight.
+type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture>>>;
+
+/// Waits until the GPU is done with the previous frame recorded in the current slot, so its per-frame resources
+/// can be reused, and returns the future the next submission has to start after.
+fn begin_frame(
+  device: &Arc<Device>,
+  fences: &mut [Option<FrameFence>],
+  slot: usize,
+  previous_slot: usize,
+) -> Resultat<Box<dyn GpuFuture>> {
+  if let Some(fence) = fences[slot].take() {
+    fence.wait(None)?;
+  }
+  Ok(match fences[previous_slot].clone() {
+    Some(fence) => fence.boxed(),
+    None => {
+      let mut now = sync::now(device.clone());
+      now.cleanup_finished();
+      now.boxed()
+    }
+  })
+}
+
+/// What a runner does after an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Recovery {
+  /// Carry on, the next frame may well succeed.
+  Retry,
+  /// Replace the device with [`recover_device`] first.
+  NewDevice,
+  Stop,
+}
+
+/// Hands `error` to the app as an [`EngineErrorEvent`] and returns how the runner can keep going.
+fn report(app: &mut App, error: EngineError) -> Recovery {
+  app.world.resource::<EngineLog>().push(LogLevel::Error, LogSource::Engine, error.to_string());
+  let recovery = if error.needs_new_device() {
+    Recovery::NewDevice
+  } else if error.is_recoverable() {
+    Recovery::Retry
+  } else {
+    Recovery::Stop
+  };
+  app.world.send_event(EngineErrorEvent(error));
+  recovery
+}
+
+/// Lets go of every frame in flight before the device is replaced. vulkano waits for a fence again when its future
+/// is dropped and panics if that fails, but its futures skip waiting while the thread unwinds, so catching that panic
+/// still releases what the frame held. The panic hook is silenced meanwhile so recovering doesn't print panics.
+/// Leaking the frame instead would keep the old swapchain alive, and with it the window in use, so the new device
+/// could never present to it.
+///
+/// With `panic = "abort"` there's no catching, so the frames are leaked and only headless apps can recover.
+fn abandon_fences(fences: &mut [Option<FrameFence>]) {
+  if cfg!(panic = "abort") {
+    fences.iter_mut().filter_map(Option::take).for_each(mem::forget);
+    return;
+  }
+  let hook = panic::take_hook();
+  panic::set_hook(Box::new(|_| {}));
+  for fence in fences.iter_mut().filter_map(Option::take) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(move || {
+      let _ = fence.wait(None);
+    }));
+  }
+  panic::set_hook(hook);
+}
+
+/// Rebuilds the device and everything on it, then tells the app with [`DeviceRecreated`] and, as the window got a new
+/// swapchain, [`SwapchainRecreated`]. The caller must have dropped its fences and image views first, and gets the
+/// replacements for what it keeps across frames.
+fn recover_device(
+  app: &mut App,
+) -> Resultat<(Arc<Device>, Arc<Queue>, StandardCommandBufferAllocator, Vec<Arc<ImageView>>)> {
+  RuminativeInternals::recreate_device(&mut app.world)?;
+  let device = app.world.resource::<ASingleton<Device>>().clon();
+  let queue = app.world.resource::<ASingleton<Queue>>().clon();
+  let command_buffer_allocator = StandardCommandBufferAllocator::new(device.clone(), Default::default());
+  let images = app.world.resource::<Singleton<Vec<Arc<Image>>>>().0.clone();
+  let views = window_size_dependent_setup(&images, &mut app.world.resource_mut::<Singleton<Viewport>>());
+  app.world.send_event(DeviceRecreated);
+  if let Some(swapchain) = app.world.get_resource::<ASingleton<Swapchain>>().map(ASingleton::clon) {
+    app.world.send_event(SwapchainRecreated {
+      extent: swapchain.image_extent(),
+      format: swapchain.image_format(),
+    });
+  }
+  Ok((device, queue, command_buffer_allocator, views))
+}
+
+/// Recreates the swapchain for the window's current size with whatever format the surface now prefers, updating
+/// `"Output"` and sending [`SwapchainRecreated`]. Returns the new images.
+fn rebuild_swapchain(app: &mut App, surface: &Surface, extent: [u32; 2]) -> Resultat<Vec<Arc<Image>>> {
+  let swapchain = app.world.resource::<ASingleton<Swapchain>>().clon();
+  let settings = &app.world.resource::<EngineSettings>().swapchain;
+  let (image_format, image_color_space) =
+    RuminativeInternals::surface_format(swapchain.device().physical_device(), surface, settings)?;
+  let (swapchain, images) = swapchain
+    .recreate(SwapchainCreateInfo {
+      image_extent: extent,
+      image_format,
+      image_color_space,
+      ..swapchain.create_info()
+    })
+    .map_err(|e| EngineError::Swapchain(e.into()))?;
+
+  // Only replaced when it changed, so systems watching it for changes don't rebuild on every resize
+  if **app.world.resource::<NamedSingleton<"Output", Format>>() != image_format {
+    app.world.insert_resource(NamedSingleton::<"Output", _>(image_format));
+  }
+  app.world.send_event(SwapchainRecreated {
+    extent: swapchain.image_extent(),
+    format: image_format,
+  });
+  app.insert_resource(ASingleton(swapchain));
+  app.insert_resource(Singleton(images.clone()));
+  Ok(images)
+}
+
+/// Writes the pipeline cache back so the next start can skip compiling, see [`EngineSettings::pipeline_cache`].
+fn save_pipeline_cache(app: &mut App) {
+  let Some(path) = app.world.resource::<EngineSettings>().pipeline_cache.clone() else {
+    return;
+  };
+  if let Some(cache) = app.world.get_resource::<ASingleton<PipelineCache>>().map(ASingleton::clon) {
+    if let Err(e) = pipeline_cache::save(&cache, path) {
+      report(app, e);
+    }
+  }
+}
+
+/// Runs one frame of the app and records the [`RenderGraph`] into a command buffer, with `output` bound to its
+/// `"Output"` attachment.
+fn record_frame(
+  app: &mut App,
+  command_buffer_allocator: &StandardCommandBufferAllocator,
+  queue: &Queue,
+  output: Arc<ImageView>,
+) -> Resultat<Arc<PrimaryAutoCommandBuffer>> {
+  let builder = AutoCommandBufferBuilder::primary(
+    command_buffer_allocator,
+    queue.queue_family_index(),
+    CommandBufferUsage::OneTimeSubmit,
+  )?;
+
+  app.world.resource_mut::<RenderGraph>().set_image("Output", output.clone());
+  app.world.insert_resource(ANamedSingleton::<"Output", _>(output));
+
+  app.insert_non_send_resource(builder);
+
+  let mut imgui = app.world.non_send_resource_mut::<Context>();
+  let ui = imgui.new_frame();
+
+  {
+    ui.dockspace_over_main_viewport();
+  }
+
+  app.update();
+  let executed = RenderGraph::execute(&mut app.world);
+
+  let builder = app
+    .world
+    .remove_non_send_resource::<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>()
+    .unwrap();
+
+  executed?;
+  Ok(builder.build()?)
+}
+
+/// How the engine presents its frames, chosen when [`RuminativeEnginePlugin`] is built.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum RenderMode {
+  /// Open a window and present to its swapchain.
+  #[default]
+  Windowed,
+  /// Render into an offscreen image without touching the windowing system, e.g. for CI or on a software driver
+  /// such as lavapipe. Runs `frames` frames, or until an [`AppExit`] event is sent when `None`.
+  Headless { extent: [u32; 2], frames: Option<u64> },
+}
+
+#[derive(Default)]
+pub struct RuminativeEnginePlugin {
+  pub settings: EngineSettings,
+}
+
+impl RuminativeEnginePlugin {
+  pub fn new(settings: EngineSettings) -> Self {
+    Self { settings }
+  }
+}
 
 impl Plugin for RuminativeEnginePlugin {
   fn build(&self, app: &mut App) {
     app.add_event::<WinitEvent>();
+    app.add_event::<EngineErrorEvent>();
+    app.add_event::<KeyPressed>();
+    app.add_event::<DeviceRecreated>();
+    app.add_event::<SwapchainRecreated>();
+    app.init_resource::<EngineLog>();
+    app.insert_resource(FrameInFlight::new(self.settings.frames_in_flight));
+    app.insert_resource(self.settings.clone());
 
-    let event_loop = EventLoop::new();
-
-    RuminativeInternals::new_in_app(&event_loop, app).unwrap();
+    RuminativeInternals::insert_persistent(app);
+    let mut requirements = app.world.resource_mut::<DeviceRequirements>();
+    requirements.require_features(Features {
+      dynamic_rendering: true,
+      ..Features::empty()
+    });
+    match self.settings.mode {
+      RenderMode::Windowed => {
+        requirements.require_extensions(DeviceExtensions {
+          khr_swapchain: true,
+          ..DeviceExtensions::empty()
+        });
+        app.insert_non_send_resource(Singleton(EventLoop::new()));
+        app.set_runner(windowed_runner);
+      }
+      RenderMode::Headless { frames, .. } => {
+        app.set_runner(move |app| headless_runner(app, frames));
+      }
+    }
 
-    app.add_event::<KeyPressed>();
+    // Pipelines register their shaders either way, they are only watched with hot reloading on
+    app.init_resource::<ShaderReloader>();
+    if self.settings.hot_reload_shaders {
+      app.add_plugins(ShaderReloadPlugin);
+    }
 
     app.init_resource::<GameViewport>();
+    app.add_plugins(GameTargetPlugin);
     app.add_plugins(RumiguiPipeline);
     app.add_plugins(ImguiPipeline);
+  }
 
-    app.insert_non_send_resource(Singleton(event_loop));
-
-    app.set_runner(|mut app| {
-      let event_loop = app
-        .world
-        .remove_non_send_resource::<Singleton<EventLoop<()>>>()
-        .unwrap()
-        .0;
-      let images = &app.world.resource::<Singleton<Vec<Arc<Image>>>>().0;
-      let mut viewport = Viewport::default();
-      let mut images = window_size_dependent_setup(images, &mut viewport);
-      app.insert_resource(Singleton(viewport));
-      let device = app.world.resource::<ASingleton<Device>>().clon();
-      let queue = app.world.resource::<ASingleton<Queue>>().clon();
-      let surface = app.world.resource::<ASingleton<Surface>>().clon();
-      let command_buffer_allocator = StandardCommandBufferAllocator::new(device.clone(), Default::default());
-      let mut recreate_swapchain = false;
-      let mut previous_frame_end = Some(sync::now(device.clone()).boxed());
-
-      event_loop.run(move |event, _a, control_flow| {
-        match event {
-          Event::WindowEvent {
-            event: WindowEvent::CloseRequested,
-            ..
-          } => {
-            *control_flow = ControlFlow::Exit;
-          }
-          Event::WindowEvent {
-            event: WindowEvent::Resized(_),
-            ..
-          } => {
-            recreate_swapchain = true;
-          }
-          Event::WindowEvent {
-            event: WindowEvent::KeyboardInput { input, .. },
-            ..
-          } => {
-            if let Some(kc) = input.virtual_keycode {
-              app.world.send_event(KeyPressed(kc));
+  /// Creates the device once every plugin had the chance to add to the [`DeviceRequirements`], then runs their
+  /// initializers.
+  fn finish(&self, app: &mut App) {
+    let created = match self.settings.mode {
+      RenderMode::Windowed => {
+        let event_loop = app.world.remove_non_send_resource::<Singleton<EventLoop<()>>>().unwrap();
+        let created = RuminativeInternals::new_in_app(&event_loop, &self.settings, app);
+        app.insert_non_send_resource(event_loop);
+        created
+      }
+      RenderMode::Headless { extent, .. } => RuminativeInternals::new_headless_in_app(extent, &self.settings, app),
+    }
+    .and_then(|()| RuminativeInternals::run_device_initializers(&mut app.world));
+    if let Err(e) = created {
+      // Nothing can render without a device or with pipelines missing. The runners see there is no device and return,
+      // and the event stays in the world for whoever inspects it afterwards.
+      app.world.resource::<EngineLog>().push(LogLevel::Error, LogSource::Engine, e.to_string());
+      app.world.send_event(EngineErrorEvent(e));
+      app.world.remove_resource::<ASingleton<Device>>();
+    }
+  }
+}
+
+/// Finishes the plugins, which creates the device, and returns whether there is a device to render with.
+fn finish_plugins(app: &mut App) -> bool {
+  app.finish();
+  app.cleanup();
+  app.world.contains_resource::<ASingleton<Device>>()
+}
+
+fn headless_runner(mut app: App, frames: Option<u64>) {
+  if !finish_plugins(&mut app) {
+    return;
+  }
+  let images = &app.world.resource::<Singleton<Vec<Arc<Image>>>>().0;
+  let mut viewport = Viewport::default();
+  let mut images = window_size_dependent_setup(images, &mut viewport);
+  app.insert_resource(Singleton(viewport));
+  let device = app.world.resource::<ASingleton<Device>>().clon();
+  let mut command_buffer_allocator = StandardCommandBufferAllocator::new(device.clone(), Default::default());
+  if let Err(e) = app.world.resource_mut::<RenderGraph>().compile() {
+    report(&mut app, e.into());
+    return;
+  }
+  let mut app_exit_reader = ManualEventReader::<AppExit>::default();
+  let mut frame = 0;
+  let mut fences: Vec<Option<FrameFence>> = vec![None; app.world.resource::<FrameInFlight>().count];
+  let mut previous_slot = 0;
+
+  while frames.map_or(true, |frames| frame < frames) {
+    let slot = app.world.resource::<FrameInFlight>().index;
+    match submit_headless_frame(&mut app, &command_buffer_allocator, &mut fences, slot, previous_slot, &images[0]) {
+      Ok(fence) => fences[slot] = Some(fence),
+      Err(e) => match report(&mut app, e) {
+        Recovery::Retry => {}
+        Recovery::NewDevice => {
+          abandon_fences(&mut fences);
+          images.clear();
+          match recover_device(&mut app) {
+            Ok((_, _, new_command_buffer_allocator, new_images)) => {
+              command_buffer_allocator = new_command_buffer_allocator;
+              images = new_images;
             }
-          }
-          Event::RedrawEventsCleared => {
-            let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
-            let dimensions = window.inner_size();
-            if dimensions.width == 0 || dimensions.height == 0 {
-              return;
+            Err(e) => {
+              report(&mut app, e);
+              break;
             }
+          }
+        }
+        Recovery::Stop => break,
+      },
+    }
+    previous_slot = slot;
+    app.world.resource_mut::<FrameInFlight>().advance();
+    frame += 1;
 
-            previous_frame_end.as_mut().unwrap().cleanup_finished();
-
-            let swapchain = app.world.resource::<ASingleton<Swapchain>>().clon();
+    if let Some(app_exit_events) = app.world.get_resource::<Events<AppExit>>() {
+      if app_exit_reader.read(app_exit_events).last().is_some() {
+        break;
+      }
+    }
+  }
 
-            if recreate_swapchain {
-              let (new_swapchain, new_images) = match swapchain.recreate(SwapchainCreateInfo {
-                image_extent: dimensions.into(),
-                ..swapchain.create_info()
-              }) {
-                Ok(r) => r,
-                Err(e) => panic!("failed to recreate swapchain: {e}"),
-              };
+  for fence in fences.iter_mut().filter_map(Option::take) {
+    if let Err(e) = fence.wait(None) {
+      report(&mut app, e.into());
+      mem::forget(fence);
+    }
+  }
+  save_pipeline_cache(&mut app);
+}
 
-              app.insert_resource(ASingleton(new_swapchain));
+fn submit_headless_frame(
+  app: &mut App,
+  command_buffer_allocator: &StandardCommandBufferAllocator,
+  fences: &mut [Option<FrameFence>],
+  slot: usize,
+  previous_slot: usize,
+  output: &Arc<ImageView>,
+) -> Resultat<FrameFence> {
+  let device = app.world.resource::<ASingleton<Device>>().clon();
+  let queue = app.world.resource::<ASingleton<Queue>>().clon();
+  let previous_future = begin_frame(&device, fences, slot, previous_slot)?;
+  let command_buffer = record_frame(app, command_buffer_allocator, &queue, output.clone())?;
+  let future = previous_future
+    .then_execute(queue, command_buffer)?
+    .boxed()
+    .then_signal_fence_and_flush()?;
+  Ok(Arc::new(future))
+}
 
-              let mut viewport = app.world.resource_mut::<Singleton<Viewport>>();
-              images = window_size_dependent_setup(&new_images, &mut viewport);
+fn windowed_runner(mut app: App) {
+  if !finish_plugins(&mut app) {
+    return;
+  }
+  let event_loop = app
+    .world
+    .remove_non_send_resource::<Singleton<EventLoop<()>>>()
+    .unwrap()
+    .0;
+  let images = &app.world.resource::<Singleton<Vec<Arc<Image>>>>().0;
+  let mut viewport = Viewport::default();
+  let mut images = window_size_dependent_setup(images, &mut viewport);
+  app.insert_resource(Singleton(viewport));
+  let mut device = app.world.resource::<ASingleton<Device>>().clon();
+  let mut queue = app.world.resource::<ASingleton<Queue>>().clon();
+  let surface = app.world.resource::<ASingleton<Surface>>().clon();
+  let mut command_buffer_allocator = StandardCommandBufferAllocator::new(device.clone(), Default::default());
+  if let Err(e) = app.world.resource_mut::<RenderGraph>().compile() {
+    report(&mut app, e.into());
+    return;
+  }
+  let mut recreate_swapchain = false;
+  let mut recovery = Recovery::Retry;
+  let mut fences: Vec<Option<FrameFence>> = vec![None; app.world.resource::<FrameInFlight>().count];
+  let mut previous_slot = 0;
 
-              recreate_swapchain = false;
-            }
+  event_loop.run(move |event, _a, control_flow| {
+    match event {
+      Event::WindowEvent {
+        event: WindowEvent::CloseRequested,
+        ..
+      } => {
+        *control_flow = ControlFlow::Exit;
+      }
+      Event::WindowEvent {
+        event: WindowEvent::Resized(_),
+        ..
+      } => {
+        recreate_swapchain = true;
+      }
+      Event::WindowEvent {
+        event: WindowEvent::KeyboardInput { input, .. },
+        ..
+      } => {
+        if let Some(kc) = input.virtual_keycode {
+          app.world.send_event(KeyPressed(kc));
+        }
+      }
+      Event::LoopDestroyed => {
+        save_pipeline_cache(&mut app);
+      }
+      Event::RedrawEventsCleared => {
+        let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
+        let dimensions = window.inner_size();
+        if dimensions.width == 0 || dimensions.height == 0 {
+          return;
+        }
 
-            let swapchain = app.world.resource::<ASingleton<Swapchain>>().clon();
-
-            let (image_index, suboptimal, acquire_future) =
-              match acquire_next_image(swapchain.clone(), None).map_err(Validated::unwrap) {
-                Ok(r) => r,
-                Err(VulkanError::OutOfDate) => {
-                  recreate_swapchain = true;
-                  return;
-                }
-                Err(e) => {
-                  panic!("failed to acquire next image: {e}")
-                }
-              };
-
-            if suboptimal {
-              recreate_swapchain = true;
+        if recreate_swapchain {
+          let new_images = match rebuild_swapchain(&mut app, &surface, dimensions.into()) {
+            Ok(r) => r,
+            Err(e) => {
+              // Usually a resize racing the window system, so try again on the next frame
+              recovery = report(&mut app, e);
+              return;
             }
-            let builder = AutoCommandBufferBuilder::primary(
-              &command_buffer_allocator,
-              queue.queue_family_index(),
-              CommandBufferUsage::OneTimeSubmit,
-            )
-              .unwrap();
+          };
 
-            app.world.insert_resource(ANamedSingleton::<"Output", _>(images[image_index as usize].clone()));
+          let mut viewport = app.world.resource_mut::<Singleton<Viewport>>();
+          images = window_size_dependent_setup(&new_images, &mut viewport);
 
-            app.insert_non_send_resource(builder);
+          recreate_swapchain = false;
+        }
 
-            let mut imgui = app.world.non_send_resource_mut::<Context>();
-            let ui = imgui.new_frame();
+        let swapchain = app.world.resource::<ASingleton<Swapchain>>().clon();
 
-            {
-              ui.dockspace_over_main_viewport();
+        let (image_index, suboptimal, acquire_future) =
+          match acquire_next_image(swapchain.clone(), None).map_err(Validated::unwrap) {
+            Ok(r) => r,
+            Err(VulkanError::OutOfDate) => {
+              recreate_swapchain = true;
+              return;
             }
-
-            app.update();
-            for i in app.world.resource::<PipelineRunner>().order.clone() {
-              app.world.run_system(i).unwrap();
+            Err(e) => {
+              recovery = report(&mut app, EngineError::Swapchain(e.into()));
+              return;
             }
+          };
 
-            let mut builder = app
-              .world
-              .remove_non_send_resource::<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>()
-              .unwrap();
-
-            builder.end_rendering().unwrap();
-            let command_buffer = builder.build().unwrap();
-
-            let future = previous_frame_end
-              .take()
-              .unwrap()
-              .join(acquire_future)
-              .then_execute(queue.clone(), command_buffer)
-              .unwrap()
-              .then_swapchain_present(
-                queue.clone(),
-                SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index),
-              )
-              .then_signal_fence_and_flush();
-
-            match future.map_err(Validated::unwrap) {
-              Ok(future) => {
-                previous_frame_end = Some(future.boxed());
-              }
-              Err(VulkanError::OutOfDate) => {
-                recreate_swapchain = true;
-                previous_frame_end = Some(sync::now(device.clone()).boxed());
-              }
-              Err(e) => {
-                dbg!(&e);
-                panic!("failed to flush future: {e}");
-              }
-            }
+        if suboptimal {
+          recreate_swapchain = true;
+        }
+
+        let slot = app.world.resource::<FrameInFlight>().index;
+        let recorded = begin_frame(&device, &mut fences, slot, previous_slot).and_then(|previous_future| {
+          let command_buffer =
+            record_frame(&mut app, &command_buffer_allocator, &queue, images[image_index as usize].clone())?;
+          Ok(previous_future.join(acquire_future).then_execute(queue.clone(), command_buffer)?)
+        });
+        previous_slot = slot;
+        app.world.resource_mut::<FrameInFlight>().advance();
+
+        let future = match recorded {
+          Ok(future) => future
+            .then_swapchain_present(
+              queue.clone(),
+              SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index),
+            )
+            .boxed()
+            .then_signal_fence_and_flush(),
+          Err(e) => {
+            recovery = report(&mut app, e);
+            return;
+          }
+        };
+
+        match future.map_err(Validated::unwrap) {
+          Ok(future) => {
+            fences[slot] = Some(Arc::new(future));
+          }
+          Err(VulkanError::OutOfDate) => {
+            recreate_swapchain = true;
+          }
+          Err(e) => {
+            recovery = report(&mut app, e.into());
           }
-          _ => (),
         }
-        if let Some(e) = event.to_static() {
-          app.world.send_event(WinitEvent(e))
+      }
+      _ => (),
+    }
+    match mem::replace(&mut recovery, Recovery::Retry) {
+      Recovery::Retry => {}
+      Recovery::NewDevice => {
+        abandon_fences(&mut fences);
+        images.clear();
+        match recover_device(&mut app) {
+          Ok((new_device, new_queue, new_command_buffer_allocator, new_images)) => {
+            device = new_device;
+            queue = new_queue;
+            command_buffer_allocator = new_command_buffer_allocator;
+            images = new_images;
+            recreate_swapchain = false;
+          }
+          Err(e) => {
+            report(&mut app, e);
+            *control_flow = ControlFlow::Exit;
+          }
         }
-      })
+      }
+      Recovery::Stop => *control_flow = ControlFlow::Exit,
+    }
+    if let Some(e) = event.to_static() {
+      app.world.send_event(WinitEvent(e))
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::device::{DeviceSelector, DeviceType};
+  use crate::engine::internals::DeviceInitializers;
+  use crate::engine::AssociatedResource;
+  use bevy_app::Update;
+  use bevy_ecs::prelude::*;
+  use std::sync::atomic::{AtomicU64, Ordering};
+  use std::sync::{Mutex, MutexGuard, PoisonError};
+  use vulkano::pipeline::GraphicsPipeline;
+
+  /// Imgui only allows one context at a time, so tests building apps take turns.
+  static APP: Mutex<()> = Mutex::new(());
+
+  fn one_app_at_a_time() -> MutexGuard<'static, ()> {
+    APP.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// A headless app on a software driver such as lavapipe, unless `RUMINATIVE_DEVICE` picks another device. `None`
+  /// when there is no Vulkan device to run on, so the test is skipped rather than failed.
+  fn headless_app(frames: Option<u64>, debug: bool) -> Option<App> {
+    let settings = EngineSettings {
+      mode: RenderMode::Headless {
+        extent: [64, 64],
+        frames,
+      },
+      device: Some(DeviceSelector::Type(DeviceType::Cpu)),
+      debug,
+      pipeline_cache: None,
+      ..Default::default()
+    };
+    let mut app = App::new();
+    app.add_plugins(RuminativeEnginePlugin::new(settings.clone()));
+    let requirements = app.world.resource::<DeviceRequirements>();
+    let usable = RuminativeInternals::list_devices(&settings, requirements)
+      .is_ok_and(|candidates| candidates.iter().any(|candidate| candidate.rejection.is_none()));
+    if !usable {
+      eprintln!("no Vulkan device to render on, skipping");
+      return None;
+    }
+    Some(app)
+  }
+
+  #[derive(Resource, Default)]
+  struct InitializerRuns(usize);
+
+  fn count_initializer_run(world: &mut World) -> Resultat<()> {
+    world.resource_mut::<InitializerRuns>().0 += 1;
+    Ok(())
+  }
+
+  #[test]
+  fn recovers_the_device() {
+    let _turn = one_app_at_a_time();
+    let Some(mut app) = headless_app(Some(1), false) else {
+      return;
+    };
+    app.init_resource::<InitializerRuns>();
+    app.world.resource_mut::<DeviceInitializers>().register(count_initializer_run);
+    assert!(finish_plugins(&mut app), "{:?}", app.world.resource::<EngineLog>().entries());
+    assert_eq!(app.world.resource::<InitializerRuns>().0, 1);
+
+    let old_device = app.world.resource::<ASingleton<Device>>().clon();
+    let mut recreated = ManualEventReader::<DeviceRecreated>::default();
+    let (device, _, command_buffer_allocator, images) = recover_device(&mut app).unwrap();
+    assert!(!Arc::ptr_eq(&device, &old_device));
+    assert_eq!(app.world.resource::<InitializerRuns>().0, 2);
+    assert_eq!(recreated.read(app.world.resource::<Events<DeviceRecreated>>()).count(), 1);
+    let pipeline = app.world.resource::<AssociatedResource<ImguiPipeline, Arc<GraphicsPipeline>>>();
+    assert!(Arc::ptr_eq(pipeline.device(), &device));
+
+    // And the new device renders
+    let mut fences = vec![None];
+    let fence = submit_headless_frame(&mut app, &command_buffer_allocator, &mut fences, 0, 0, &images[0]).unwrap();
+    fence.wait(None).unwrap();
+    assert_eq!(app.world.resource::<EngineLog>().errors(), 0);
+  }
+
+  #[test]
+  fn keeps_the_old_device_when_no_new_one_can_be_made() {
+    let _turn = one_app_at_a_time();
+    if DeviceSelector::from_env().is_some() {
+      return;
+    }
+    let Some(mut app) = headless_app(Some(1), false) else {
+      return;
+    };
+    assert!(finish_plugins(&mut app), "{:?}", app.world.resource::<EngineLog>().entries());
+    let old_device = app.world.resource::<ASingleton<Device>>().clon();
+    let old_image = app.world.resource::<Singleton<Vec<Arc<Image>>>>()[0].clone();
+
+    app.world.resource_mut::<EngineSettings>().device = Some(DeviceSelector::Name("no such device".to_string()));
+    assert!(RuminativeInternals::recreate_device(&mut app.world).is_err());
+    assert!(Arc::ptr_eq(&app.world.resource::<ASingleton<Device>>().0, &old_device));
+    assert!(Arc::ptr_eq(&app.world.resource::<Singleton<Vec<Arc<Image>>>>()[0], &old_image));
+  }
+
+  #[test]
+  fn renders_headless() {
+    let _turn = one_app_at_a_time();
+    let Some(mut app) = headless_app(Some(3), false) else {
+      return;
+    };
+    let updates = Arc::new(AtomicU64::new(0));
+    let counter = updates.clone();
+    app.add_systems(Update, move || {
+      counter.fetch_add(1, Ordering::Relaxed);
     });
+    let log = app.world.resource::<EngineLog>().clone();
+    app.run();
+    assert_eq!(log.errors(), 0, "{:#?}", log.entries());
+    assert_eq!(updates.load(Ordering::Relaxed), 3);
+  }
+
+  #[test]
+  fn renders_headless_without_validation_errors() {
+    let _turn = one_app_at_a_time();
+    let Some(mut app) = headless_app(Some(3), true) else {
+      return;
+    };
+    let log = app.world.resource::<EngineLog>().clone();
+    app.run();
+    assert_eq!(log.validation_errors(), 0, "{:#?}", log.entries());
+    assert_eq!(log.errors(), 0, "{:#?}", log.entries());
   }
 }
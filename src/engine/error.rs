@@ -0,0 +1,186 @@
+use crate::engine::render_graph::RenderGraphError;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::RegisteredSystemError;
+use std::error::Error;
+use std::fmt;
+use std::iter;
+use vulkano::buffer::AllocateBufferError;
+use vulkano::command_buffer::CommandBufferExecError;
+use vulkano::image::AllocateImageError;
+use vulkano::memory::allocator::MemoryAllocatorError;
+use vulkano::pipeline::layout::IntoPipelineLayoutCreateInfoError;
+use vulkano::sync::HostAccessError;
+use vulkano::{LoadingError, Validated, ValidationError, VulkanError};
+
+pub type BoxedError = Box<dyn Error + Send + Sync>;
+
+/// Everything that can go wrong in the engine. The first few variants say which step failed and wrap whatever
+/// error caused it, the others come straight from vulkano or the render graph.
+#[derive(Debug)]
+pub enum EngineError {
+  DeviceSelection(BoxedError),
+  Surface(BoxedError),
+  Swapchain(BoxedError),
+  ShaderLoad(BoxedError),
+  PipelineCreation(BoxedError),
+  Allocation(BoxedError),
+  RenderGraph(RenderGraphError),
+  Vulkan(VulkanError),
+  Validation(Box<ValidationError>),
+  Other(BoxedError),
+}
+
+impl EngineError {
+  /// The [`VulkanError`] at the root of this error, looking through the step variants and their sources.
+  pub fn vulkan_error(&self) -> Option<VulkanError> {
+    match self {
+      EngineError::Vulkan(e) => Some(*e),
+      EngineError::DeviceSelection(source)
+      | EngineError::Surface(source)
+      | EngineError::Swapchain(source)
+      | EngineError::ShaderLoad(source)
+      | EngineError::PipelineCreation(source)
+      | EngineError::Allocation(source)
+      | EngineError::Other(source) => {
+        iter::successors(Some(source.as_ref() as &(dyn Error + 'static)), |e| e.source()).find_map(|e| {
+          if let Some(e) = e.downcast_ref::<VulkanError>() {
+            Some(*e)
+          } else if let Some(Validated::Error(e)) = e.downcast_ref::<Validated<VulkanError>>() {
+            Some(*e)
+          } else {
+            None
+          }
+        })
+      }
+      _ => None,
+    }
+  }
+
+  /// Whether trying again on a later frame can succeed. The runners retry recoverable errors and stop on others.
+  pub fn is_recoverable(&self) -> bool {
+    match self {
+      EngineError::DeviceSelection(_) | EngineError::Surface(_) => false,
+      _ => !matches!(
+        self.vulkan_error(),
+        Some(VulkanError::DeviceLost | VulkanError::SurfaceLost | VulkanError::InitializationFailed)
+      ),
+    }
+  }
+
+  /// Whether the device has to be replaced before anything can render again, i.e. it was lost or ran out of
+  /// memory. The runners then rebuild it with [`RuminativeInternals::recreate_device`].
+  ///
+  /// [`RuminativeInternals::recreate_device`]: crate::engine::internals::RuminativeInternals::recreate_device
+  pub fn needs_new_device(&self) -> bool {
+    match self {
+      EngineError::DeviceSelection(_) | EngineError::Surface(_) => false,
+      _ => matches!(
+        self.vulkan_error(),
+        Some(VulkanError::DeviceLost | VulkanError::OutOfDeviceMemory | VulkanError::OutOfHostMemory)
+      ),
+    }
+  }
+}
+
+impl fmt::Display for EngineError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EngineError::DeviceSelection(e) => write!(f, "failed to select a device: {e}"),
+      EngineError::Surface(e) => write!(f, "failed to create the window surface: {e}"),
+      EngineError::Swapchain(e) => write!(f, "swapchain error: {e}"),
+      EngineError::ShaderLoad(e) => write!(f, "failed to load shader: {e}"),
+      EngineError::PipelineCreation(e) => write!(f, "failed to create pipeline: {e}"),
+      EngineError::Allocation(e) => write!(f, "allocation failed: {e}"),
+      EngineError::RenderGraph(e) => write!(f, "render graph error: {e}"),
+      EngineError::Vulkan(e) => write!(f, "vulkan error: {e}"),
+      EngineError::Validation(e) => write!(f, "validation error: {e}"),
+      EngineError::Other(e) => write!(f, "{e}"),
+    }
+  }
+}
+
+impl Error for EngineError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      EngineError::DeviceSelection(e)
+      | EngineError::Surface(e)
+      | EngineError::Swapchain(e)
+      | EngineError::ShaderLoad(e)
+      | EngineError::PipelineCreation(e)
+      | EngineError::Allocation(e)
+      | EngineError::Other(e) => Some(e.as_ref()),
+      EngineError::RenderGraph(e) => Some(e),
+      EngineError::Vulkan(e) => Some(e),
+      EngineError::Validation(e) => Some(e.as_ref()),
+    }
+  }
+}
+
+impl From<VulkanError> for EngineError {
+  fn from(e: VulkanError) -> Self {
+    EngineError::Vulkan(e)
+  }
+}
+
+impl From<Box<ValidationError>> for EngineError {
+  fn from(e: Box<ValidationError>) -> Self {
+    EngineError::Validation(e)
+  }
+}
+
+impl From<Validated<VulkanError>> for EngineError {
+  fn from(e: Validated<VulkanError>) -> Self {
+    match e {
+      Validated::Error(e) => EngineError::Vulkan(e),
+      Validated::ValidationError(e) => EngineError::Validation(e),
+    }
+  }
+}
+
+impl From<RenderGraphError> for EngineError {
+  fn from(e: RenderGraphError) -> Self {
+    EngineError::RenderGraph(e)
+  }
+}
+
+impl From<&str> for EngineError {
+  fn from(e: &str) -> Self {
+    EngineError::Other(e.into())
+  }
+}
+
+impl From<String> for EngineError {
+  fn from(e: String) -> Self {
+    EngineError::Other(e.into())
+  }
+}
+
+macro_rules! from_error {
+  ($variant:ident: $($error:ty),* $(,)?) => {
+    $(
+      impl From<$error> for EngineError {
+        fn from(e: $error) -> Self {
+          EngineError::$variant(Box::new(e))
+        }
+      }
+    )*
+  };
+}
+
+from_error!(Allocation: Validated<AllocateBufferError>, Validated<AllocateImageError>, MemoryAllocatorError);
+from_error!(
+  Other: LoadingError,
+  HostAccessError,
+  CommandBufferExecError,
+  IntoPipelineLayoutCreateInfoError,
+  RegisteredSystemError,
+  winit::error::OsError,
+  png::DecodingError,
+  ron::error::SpannedError,
+  std::io::Error,
+);
+
+/// Sent for every error that reaches [`handle_result`](crate::engine::handle_result) or the runner, so apps can
+/// show or log them.
+#[derive(Event, Debug)]
+pub struct EngineErrorEvent(pub EngineError);
@@ -0,0 +1,58 @@
+use bevy_ecs::prelude::*;
+
+/// The frame currently being recorded, out of `count` frames the GPU may work on at once. The runner waits for the
+/// GPU to finish the previous frame with the same `index` before updating the app, so anything stored in that
+/// frame's slot of a [`PerFrame`] is free to overwrite.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FrameInFlight {
+  pub index: usize,
+  pub count: usize,
+}
+
+impl FrameInFlight {
+  pub fn new(count: usize) -> Self {
+    assert!(count > 0, "at least one frame has to be in flight");
+    Self { index: 0, count }
+  }
+
+  /// Moves on to the next slot, wrapping around after `count` frames.
+  pub fn advance(&mut self) {
+    self.index = (self.index + 1) % self.count;
+  }
+}
+
+/// One `T` per frame in flight, for buffers and descriptor sets the GPU may still be reading while the next frame
+/// is recorded.
+pub struct PerFrame<T> {
+  slots: Vec<T>,
+}
+
+impl<T> PerFrame<T> {
+  pub fn new(frame: &FrameInFlight, mut slot: impl FnMut(usize) -> T) -> Self {
+    Self {
+      slots: (0..frame.count).map(&mut slot).collect(),
+    }
+  }
+
+  pub fn current(&self, frame: &FrameInFlight) -> &T {
+    &self.slots[frame.index]
+  }
+
+  pub fn current_mut(&mut self, frame: &FrameInFlight) -> &mut T {
+    &mut self.slots[frame.index]
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &T> {
+    self.slots.iter()
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+    self.slots.iter_mut()
+  }
+}
+
+impl<T: Default> PerFrame<T> {
+  pub fn from_default(frame: &FrameInFlight) -> Self {
+    Self::new(frame, |_| T::default())
+  }
+}
@@ -0,0 +1,132 @@
+use crate::engine::internals::DeviceInitializers;
+use crate::engine::render_graph::{RenderGraph, RenderNode};
+use crate::engine::texture::Texture;
+use crate::engine::{debug_name, handle_result, ASingleton, GameViewport, NamedSingleton, Resultat};
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_ecs::prelude::*;
+use imgui::Context;
+use std::sync::Arc;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+/// Offscreen color target game passes render into, exposed to the render graph as the `"Game"` attachment. It
+/// follows the size of [`GameViewport`] in physical pixels and has the same format as `"Output"`, so pipelines
+/// built for one work with the other.
+#[derive(Resource, Default)]
+pub struct GameTarget {
+  texture: Option<Texture>,
+}
+
+impl GameTarget {
+  pub fn texture(&self) -> Option<&Texture> {
+    self.texture.as_ref()
+  }
+
+  pub fn extent(&self) -> [u32; 2] {
+    self.texture.as_ref().map_or([0, 0], Texture::extent)
+  }
+
+  /// Viewport covering the whole target, for passes writing `"Game"`.
+  pub fn viewport(&self) -> Viewport {
+    let [width, height] = self.extent();
+    Viewport {
+      extent: [width as f32, height as f32],
+      ..Default::default()
+    }
+  }
+
+  fn image(
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    format: Format,
+    extent: [u32; 2],
+  ) -> Resultat<Arc<ImageView>> {
+    let image = Image::new(
+      memory_allocator,
+      ImageCreateInfo {
+        image_type: ImageType::Dim2d,
+        format,
+        extent: [extent[0], extent[1], 1],
+        usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+        ..Default::default()
+      },
+      AllocationCreateInfo {
+        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+        ..Default::default()
+      },
+    )?;
+    debug_name::<Self>(&*image, "image");
+    let view = ImageView::new_default(image)?;
+    debug_name::<Self>(&*view, "view");
+    Ok(view)
+  }
+
+  fn resize(
+    device: Res<ASingleton<Device>>,
+    memory_allocator: Res<ASingleton<StandardMemoryAllocator>>,
+    output_format: Res<NamedSingleton<"Output", Format>>,
+    game_viewport: Res<GameViewport>,
+    imgui: NonSend<Context>,
+    mut game_target: ResMut<GameTarget>,
+    mut render_graph: ResMut<RenderGraph>,
+  ) -> Resultat<()> {
+    let scale = imgui.io().display_framebuffer_scale;
+    let extent = [
+      ((game_viewport.size[0] * scale[0]).round() as u32).max(1),
+      ((game_viewport.size[1] * scale[1]).round() as u32).max(1),
+    ];
+
+    // The format changes when the swapchain is recreated for another monitor
+    if game_target.texture.is_none() || game_target.extent() != extent || output_format.is_changed() {
+      let view = Self::image(memory_allocator.clon(), **output_format, extent)?;
+      let sampler = match &game_target.texture {
+        Some(texture) => texture.sampler.clone(),
+        None => {
+          let sampler = Sampler::new(
+            device.clon(),
+            SamplerCreateInfo {
+              mag_filter: Filter::Nearest,
+              min_filter: Filter::Nearest,
+              address_mode: [SamplerAddressMode::ClampToEdge; 3],
+              ..Default::default()
+            },
+          )?;
+          debug_name::<Self>(&*sampler, "sampler");
+          sampler
+        }
+      };
+      game_target.texture = Some(Texture { view, sampler });
+    }
+
+    render_graph.set_image("Game", game_target.texture.as_ref().unwrap().view.clone());
+    Ok(())
+  }
+
+  /// Drops the target made on the old device, `resize` creates a new one on the next frame.
+  fn reset(world: &mut World) -> Resultat<()> {
+    world.insert_resource(GameTarget::default());
+    Ok(())
+  }
+
+  /// Does nothing but gives the `"Game"` attachment a writer, so it is cleared even when no game pass is registered.
+  fn clear() {}
+}
+
+pub struct GameTargetPlugin;
+
+impl Plugin for GameTargetPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<GameTarget>();
+    app.world.resource_mut::<DeviceInitializers>().register(GameTarget::reset);
+    app.add_systems(PostUpdate, GameTarget::resize.pipe(handle_result));
+
+    let system_id = app.world.register_system(GameTarget::clear);
+    let mut render_graph = app.world.resource_mut::<RenderGraph>();
+    render_graph.declare_attachment("Game", Some([0.0, 0.0, 0.0, 1.0]));
+    render_graph.add_pass(RenderNode::new("game", system_id).writes("Game"));
+  }
+}
@@ -1,40 +1,45 @@
-use crate::engine::{handle_result, ASingleton, AssociatedResource, PipelineRunner, Resultat, WinitEvent, Singleton, ANamedSingleton};
-use bevy_app::{App, Plugin, PostUpdate, PreUpdate};
+use crate::engine::device::{DeviceRequirements, EnabledOptionals};
+use crate::engine::frames::{FrameInFlight, PerFrame};
+use crate::engine::game_target::GameTarget;
+use crate::engine::internals::DeviceInitializers;
+use crate::engine::render_graph::RenderNode;
+use crate::engine::render_pipeline::{RenderPipeline, RenderPipelinePlugin};
+use crate::engine::texture::Texture;
+use crate::engine::{
+  debug_name, handle_result, ASingleton, AssociatedResource, Resultat, WinitEvent, Singleton, NamedSingleton,
+};
+use bevy_app::{App, Last, Plugin, PostUpdate, PreUpdate};
 use bevy_ecs::prelude::*;
-use imgui::{BackendFlags, ConfigFlags, Context, DrawCmd, DrawVert, FontAtlasTexture, FontSource, Io, Key};
+use bevy_ecs::system::SystemId;
+use hashbrown::HashMap;
+use imgui::{
+  BackendFlags, ConfigFlags, Context, DrawCmd, DrawIdx, DrawVert, FontAtlasTexture, FontSource, Io, Key, TextureId,
+  WindowToken,
+};
+use imgui_sys::{igGetWindowDrawList, ImDrawCmd, ImDrawList, ImDrawList_AddCallback};
 use smallvec::smallvec;
 use std::cmp::Ordering;
-use std::error::Error;
+use std::ffi::c_void;
+use std::mem::{self, size_of};
 use std::slice;
 use std::sync::Arc;
-use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
+use vulkano::buffer::{BufferContents, BufferUsage, Subbuffer};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferToImageInfo, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract, RenderingAttachmentInfo, RenderingInfo};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
 use vulkano::descriptor_set::allocator::{StandardDescriptorSetAllocator};
-use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
-use vulkano::device::{Device, Queue};
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::{Device, Features, Queue};
 use vulkano::format::Format;
-use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
-use vulkano::image::view::ImageView;
-use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
-use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
-use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState};
-use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
-use vulkano::pipeline::graphics::multisample::MultisampleState;
-use vulkano::pipeline::graphics::rasterization::RasterizationState;
-use vulkano::pipeline::graphics::subpass::PipelineRenderingCreateInfo;
-use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexDefinition};
-use vulkano::pipeline::graphics::viewport::{Scissor, Viewport, ViewportState};
-use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
-use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
-use vulkano::pipeline::{
-  DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo,
-};
+use vulkano::image::sampler::{Filter, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::Image;
+use vulkano::memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexBufferDescription};
+use vulkano::pipeline::graphics::viewport::{Scissor, Viewport};
+use vulkano::pipeline::{DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint};
 use vulkano::shader::EntryPoint;
-use vulkano::swapchain::{Surface, Swapchain};
-use vulkano::sync::GpuFuture;
+use vulkano::swapchain::Surface;
 use vulkano::DeviceSize;
-use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
 use winit::event::{
   DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, TouchPhase, VirtualKeyCode,
   WindowEvent,
@@ -182,14 +187,13 @@ fn handle_window_event(io: &mut Io, window: &Window, event: &WindowEvent) {
       io.display_size = [logical_size.width, logical_size.height];
     }
     WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
-      let hidpi_factor = scale_factor;
+      let hidpi_factor = scale_factor as f32;
+      // The cursor stays on the same physical pixel, so its logical position moves by old / new scale
       if io.mouse_pos[0].is_finite() && io.mouse_pos[1].is_finite() {
-        io.mouse_pos = [
-          io.mouse_pos[0] * window.scale_factor() as f32,
-          io.mouse_pos[1] * window.scale_factor() as f32,
-        ];
+        let ratio = io.display_framebuffer_scale[0] / hidpi_factor;
+        io.mouse_pos = [io.mouse_pos[0] * ratio, io.mouse_pos[1] * ratio];
       }
-      io.display_framebuffer_scale = [hidpi_factor as f32, hidpi_factor as f32];
+      io.display_framebuffer_scale = [hidpi_factor, hidpi_factor];
       let logical_size = window.inner_size().to_logical(scale_factor);
       io.display_size = [logical_size.width, logical_size.height];
     }
@@ -266,9 +270,175 @@ fn handle_window_event(io: &mut Io, window: &Window, event: &WindowEvent) {
   }
 }
 
+/// Converts an imgui clip rect, in logical coordinates relative to `display_pos`, into a scissor in framebuffer
+/// pixels. The rect is scaled by imgui's `display_framebuffer_scale`, grown to whole pixels and clamped to the
+/// framebuffer. Returns `None` when none of it is visible.
+pub fn clip_rect_to_scissor(
+  clip_rect: [f32; 4],
+  display_pos: [f32; 2],
+  framebuffer_scale: [f32; 2],
+  framebuffer_extent: [u32; 2],
+) -> Option<Scissor> {
+  let min = [
+    ((clip_rect[0] - display_pos[0]) * framebuffer_scale[0]).floor().max(0.0),
+    ((clip_rect[1] - display_pos[1]) * framebuffer_scale[1]).floor().max(0.0),
+  ];
+  let max = [
+    ((clip_rect[2] - display_pos[0]) * framebuffer_scale[0]).ceil().min(framebuffer_extent[0] as f32),
+    ((clip_rect[3] - display_pos[1]) * framebuffer_scale[1]).ceil().min(framebuffer_extent[1] as f32),
+  ];
+  if max[0] <= min[0] || max[1] <= min[1] {
+    return None;
+  }
+  Some(Scissor {
+    offset: [min[0] as u32, min[1] as u32],
+    extent: [(max[0] - min[0]) as u32, (max[1] - min[1]) as u32],
+  })
+}
+
 pub struct ImguiPipeline;
 
-#[derive(BufferContents, Vertex, Clone)]
+pub const FONT_TEXTURE_ID: TextureId = TextureId::new(0);
+/// Shows the [`GameTarget`] when passed to `imgui::Image`.
+pub const GAME_TEXTURE_ID: TextureId = TextureId::new(1);
+
+/// Textures imgui can draw, keyed by the [`TextureId`] handed to `imgui::Image` and friends. Register an image
+/// view and sampler once, then pass the returned id to imgui every frame.
+#[derive(Resource)]
+pub struct ImguiTextures {
+  textures: HashMap<usize, Texture>,
+  next_id: usize,
+}
+
+impl Default for ImguiTextures {
+  fn default() -> Self {
+    Self {
+      textures: HashMap::new(),
+      next_id: GAME_TEXTURE_ID.id() + 1,
+    }
+  }
+}
+
+impl ImguiTextures {
+  pub fn register(&mut self, texture: Texture) -> TextureId {
+    let id = TextureId::new(self.next_id);
+    self.next_id += 1;
+    self.textures.insert(id.id(), texture);
+    id
+  }
+
+  /// Points `id` at another texture, e.g. after a render target was recreated with a new size.
+  pub fn replace(&mut self, id: TextureId, texture: Texture) {
+    self.textures.insert(id.id(), texture);
+  }
+
+  pub fn unregister(&mut self, id: TextureId) -> Option<Texture> {
+    self.textures.remove(&id.id())
+  }
+
+  pub fn get(&self, id: TextureId) -> Option<&Texture> {
+    self.textures.get(&id.id())
+  }
+}
+
+/// Arena size the imgui buffers start with, enough for a handful of plain windows.
+const INITIAL_ARENA_SIZE: DeviceSize = 64 * 1024;
+
+/// What [`ImguiPipeline`] uploaded for the last frame, shown in the editor's menu bar.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ImguiStats {
+  pub uploaded_bytes: DeviceSize,
+  pub arena_size: DeviceSize,
+}
+
+impl Default for ImguiStats {
+  fn default() -> Self {
+    Self {
+      uploaded_bytes: 0,
+      arena_size: INITIAL_ARENA_SIZE,
+    }
+  }
+}
+
+/// Where an [`ImguiCallback`] runs: inside the clip rect of the window that queued it.
+pub struct ImguiCallbackInfo {
+  /// Clip rect in imgui's logical coordinates.
+  pub clip_rect: [f32; 4],
+  /// `clip_rect` in framebuffer pixels, already set as scissor 0.
+  pub scissor: Scissor,
+  pub viewport: Viewport,
+  pub output_format: Format,
+}
+
+pub type ImguiCallback = Box<
+  dyn FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, &ImguiCallbackInfo) -> Resultat<()>
+    + Send
+    + Sync,
+>;
+
+/// Custom drawing queued from editor panels. The callback is recorded between imgui's own draws, inside the
+/// `"Output"` rendering scope, and may bind any pipeline built for [`ImguiCallbackInfo::output_format`]. Imgui's
+/// pipeline state is bound again once it returns.
+#[derive(Resource, Default)]
+pub struct ImguiCallbacks {
+  pending: Vec<ImguiCallback>,
+}
+
+/// Tags draw list callbacks that index into [`ImguiCallbacks`]. Never actually called.
+unsafe extern "C" fn queued_callback(_: *const ImDrawList, _: *const ImDrawCmd) {}
+
+impl ImguiCallbacks {
+  /// Inserts `callback` into the draw list of the window currently being built, so it is drawn above what the
+  /// window drew so far and below what it draws afterwards. Inside a child window that is the child's draw list.
+  pub fn add(
+    &mut self,
+    _window: &WindowToken<'_>,
+    callback: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, &ImguiCallbackInfo) -> Resultat<()>
+      + Send
+      + Sync
+      + 'static,
+  ) {
+    let index = self.pending.len();
+    self.pending.push(Box::new(callback));
+    // A live window token means a window was begun and not yet ended, so this isn't imgui's implicit debug window
+    unsafe { ImDrawList_AddCallback(igGetWindowDrawList(), Some(queued_callback), index as *mut c_void) };
+  }
+}
+
+enum ImguiDrawCommand {
+  Elements {
+    texture_id: usize,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    clip_rect: [f32; 4],
+  },
+  ResetRenderState,
+  Callback {
+    callback: ImguiCallback,
+    clip_rect: [f32; 4],
+  },
+}
+
+/// Geometry of one frame in flight. All draw lists share one vertex and one index slice, so draw commands carry
+/// offsets into those rather than a draw list index.
+///
+/// Indices use whatever [`DrawIdx`] imgui was built with. With 16-bit indices imgui starts a new command with a
+/// fresh `vtx_offset` before a draw list goes past 65 535 vertices, which only works because the backend sets
+/// `RENDERER_HAS_VTX_OFFSET` and every draw passes the offset on as its vertex offset. 32-bit indices never get
+/// split, so without `full_draw_index_uint32`, where devices only have to handle index values below 2^24, each
+/// command's indices are rebased to start from 0 and the difference moves into its vertex offset.
+#[derive(Default)]
+struct ImguiFrame {
+  vertices: Option<Subbuffer<[DrawVertPod]>>,
+  indices: Option<Subbuffer<[DrawIdx]>>,
+  draw_commands: Vec<ImguiDrawCommand>,
+  display_pos: [f32; 2],
+  display_size: [f32; 2],
+  framebuffer_scale: [f32; 2],
+}
+
+#[derive(BufferContents, Vertex, Clone, Copy)]
 #[repr(C)]
 pub struct DrawVertPod {
   #[format(R32G32_SFLOAT)]
@@ -282,330 +452,389 @@ pub struct DrawVertPod {
 mod vs {
   vulkano_shaders::shader! {
       ty: "vertex",
-      path: "assets/shaders/imgui_vertex.glsl"
+      path: "assets/shaders/imgui_vertex.glsl",
+      include: ["assets/shaders/lib"]
   }
 }
 
 mod fs {
   vulkano_shaders::shader! {
       ty: "fragment",
-      path: "assets/shaders/imgui_fragment.glsl"
+      path: "assets/shaders/imgui_fragment.glsl",
+      include: ["assets/shaders/lib"]
   }
 }
 
-impl ImguiPipeline {
-  fn shaders(device: Arc<Device>) -> Result<(EntryPoint, EntryPoint), Box<dyn Error>> {
+impl RenderPipeline for ImguiPipeline {
+  const NAME: &'static str = "imgui";
+  const VERTEX_SHADER: &'static str = "assets/shaders/imgui_vertex.glsl";
+  const FRAGMENT_SHADER: &'static str = "assets/shaders/imgui_fragment.glsl";
+  const DYNAMIC_STATE: &'static [DynamicState] = &[DynamicState::Scissor, DynamicState::Viewport];
+
+  fn shaders(device: Arc<Device>) -> Resultat<(EntryPoint, EntryPoint)> {
     let vs = vs::load(device.clone())?.entry_point("main").unwrap();
     let fs = fs::load(device)?.entry_point("main").unwrap();
     Ok((vs, fs))
   }
 
-  fn sampler(
-    device: Arc<Device>,
+  fn vertex_buffer() -> VertexBufferDescription {
+    DrawVertPod::per_vertex()
+  }
+
+  fn bind_system(world: &mut World) -> SystemId {
+    world.register_system(ImguiPipeline::bind.pipe(handle_result))
+  }
+
+  fn node(node: RenderNode) -> RenderNode {
+    node.reads("Game").writes("Output")
+  }
+}
+
+impl ImguiPipeline {
+  fn font_texture(
     queue: Arc<Queue>,
-    pipeline: Arc<GraphicsPipeline>,
     memory_allocator: Arc<StandardMemoryAllocator>,
     tex: FontAtlasTexture,
     command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
-  ) -> Result<(Arc<PersistentDescriptorSet>, Option<Box<dyn GpuFuture>>), Box<dyn Error>> {
-    let mut uploads = AutoCommandBufferBuilder::primary(
-      &command_buffer_allocator,
-      queue.queue_family_index(),
-      CommandBufferUsage::OneTimeSubmit,
-    )?;
-
-    let upload_buffer = Buffer::new_slice(
-      memory_allocator.clone(),
-      BufferCreateInfo {
-        usage: BufferUsage::TRANSFER_SRC,
-        ..Default::default()
-      },
-      AllocationCreateInfo {
-        memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-        ..Default::default()
-      },
-      (tex.width * tex.height * 4) as DeviceSize,
-    )?;
-
-    upload_buffer.write()?.copy_from_slice(tex.data);
-
-    let texture = {
-      let image = Image::new(
-        memory_allocator,
-        ImageCreateInfo {
-          usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
-          format: Format::R8G8B8A8_SRGB,
-          image_type: ImageType::Dim2d,
-          extent: [tex.width, tex.height, 1],
-          ..Default::default()
-        },
-        AllocationCreateInfo {
-          memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
-          ..Default::default()
-        },
-      )?;
-
-      uploads.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(upload_buffer, image.clone()))?;
-      ImageView::new_default(image)?
-    };
-
-    let sampler = Sampler::new(
-      device,
+  ) -> Resultat<Texture> {
+    Texture::from_rgba8(
+      queue,
+      memory_allocator,
+      command_buffer_allocator,
+      [tex.width, tex.height],
+      tex.data,
       SamplerCreateInfo {
         mag_filter: Filter::Linear,
         min_filter: Filter::Linear,
         address_mode: [SamplerAddressMode::Repeat; 3],
         ..Default::default()
       },
-    )?;
-
-    let layout = pipeline.layout().set_layouts().get(0).unwrap();
-    let set = PersistentDescriptorSet::new(
-      &descriptor_set_allocator,
-      layout.clone(),
-      [WriteDescriptorSet::image_view_sampler(0, texture, sampler)],
-      [],
-    )?;
-
-    let previous_frame_end = Some(uploads.build()?.execute(queue)?.boxed());
-    previous_frame_end.unwrap().flush()?;
-
-    Ok((set, None))
-  }
-
-  fn pipeline(
-    device: Arc<Device>,
-    swapchain: Arc<Swapchain>,
-    vs: EntryPoint,
-    fs: EntryPoint,
-  ) -> Result<Arc<GraphicsPipeline>, Box<dyn Error>> {
-    let vertex_input_state = DrawVertPod::per_vertex().definition(&vs.info().input_interface)?;
-    let stages = smallvec![
-      PipelineShaderStageCreateInfo::new(vs),
-      PipelineShaderStageCreateInfo::new(fs),
-    ];
-    let layout = PipelineLayout::new(
-      device.clone(),
-      PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages).into_pipeline_layout_create_info(device.clone())?,
-    )?;
-    let subpass = PipelineRenderingCreateInfo {
-      color_attachment_formats: vec![Some(swapchain.image_format())],
-      ..Default::default()
-    };
-    let pipeline = GraphicsPipeline::new(
-      device.clone(),
-      None,
-      GraphicsPipelineCreateInfo {
-        stages,
-        vertex_input_state: Some(vertex_input_state),
-        input_assembly_state: Some(InputAssemblyState::default()),
-        viewport_state: Some(ViewportState::default()),
-        rasterization_state: Some(RasterizationState::default()),
-        multisample_state: Some(MultisampleState::default()),
-        color_blend_state: Some(ColorBlendState::with_attachment_states(
-          subpass.color_attachment_formats.len() as u32,
-          ColorBlendAttachmentState {
-            blend: Some(AttachmentBlend::alpha()),
-            ..Default::default()
-          },
-        )),
-        dynamic_state: [DynamicState::Scissor, DynamicState::Viewport].into_iter().collect(),
-        subpass: Some(subpass.into()),
-        ..GraphicsPipelineCreateInfo::layout(layout)
-      },
-    )?;
-    Ok(pipeline)
+    )
   }
 
-  fn init(app: &mut App) -> Resultat<()> {
-    let memory_allocator = app.world.resource::<ASingleton<StandardMemoryAllocator>>();
-    let device = app.world.resource::<ASingleton<Device>>();
-    let swapchain = app.world.resource::<ASingleton<Swapchain>>();
-    let queue = app.world.resource::<ASingleton<Queue>>();
-    let descriptor_set_allocator = app.world.resource::<ASingleton<StandardDescriptorSetAllocator>>();
-    let command_buffer_allocator = app.world.resource::<ASingleton<StandardCommandBufferAllocator>>();
-
-    let (vs, fs) = Self::shaders(device.clon())?;
-    let pipeline = Self::pipeline(device.clon(), swapchain.clon(), vs, fs)?;
-
+  fn init(app: &mut App) {
     let mut imgui = Context::create();
     imgui.fonts().add_font(&[FontSource::DefaultFontData { config: None }]);
-    let tex = imgui.fonts().build_rgba32_texture();
-
-    let (descriptor_set, _previous_frame_end) = Self::sampler(
-      device.clon(),
-      queue.clon(),
-      pipeline.clone(),
-      memory_allocator.clon(),
-      tex,
-      command_buffer_allocator.clon(),
-      descriptor_set_allocator.clon()
-    )?;
+    imgui.fonts().tex_id = FONT_TEXTURE_ID;
 
     let io = imgui.io_mut();
-    io.display_size = [800.0, 600.0];
-    io.display_framebuffer_scale = [2.0, 2.0];
     io.backend_flags.insert(BackendFlags::HAS_MOUSE_CURSORS);
     io.backend_flags.insert(BackendFlags::HAS_SET_MOUSE_POS);
+    // Lets imgui split draw lists past the 16-bit index range instead of wrapping indices around
     io.backend_flags.insert(BackendFlags::RENDERER_HAS_VTX_OFFSET);
     io.config_flags.insert(ConfigFlags::DOCKING_ENABLE);
     imgui.set_platform_name(Some(format!("imgui-winit-support {}", env!("CARGO_PKG_VERSION"))));
     imgui.set_renderer_name(Some(format!("imgui-glium-renderer {}", env!("CARGO_PKG_VERSION"))));
 
-    app.insert_resource(AssociatedResource::<Self, _>::new(pipeline));
-    app.insert_resource(AssociatedResource::<Self, _>::new(descriptor_set));
-    app.insert_resource(AssociatedResource::<Self, Vec<Subbuffer<[u16]>>>::new(vec![]));
-    app.insert_resource(AssociatedResource::<Self, Vec<Subbuffer<[DrawVertPod]>>>::new(vec![]));
-    app.insert_resource(AssociatedResource::<Self, Vec<(usize, usize, u32, u32, i32, [f32; 4])>>::new(
-      vec![],
-    ));
+    app.insert_non_send_resource(imgui);
+    app.init_resource::<ImguiTextures>();
+    app.init_resource::<ImguiStats>();
+    app.init_resource::<ImguiCallbacks>();
+  }
+
+  /// Sizes the display after the window or offscreen image exists and starts the first frame.
+  fn start(app: &mut App) {
+    let surface = app.world.get_resource::<ASingleton<Surface>>().map(ASingleton::clon);
+    let images = app.world.get_resource::<Singleton<Vec<Arc<Image>>>>().map(|images| images[0].clone());
+    let mut imgui = app.world.non_send_resource_mut::<Context>();
+    let io = imgui.io_mut();
+    if let Some(surface) = surface {
+      let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
+      let scale_factor = window.scale_factor();
+      let logical_size = window.inner_size().to_logical::<f32>(scale_factor);
+      io.display_size = [logical_size.width, logical_size.height];
+      io.display_framebuffer_scale = [scale_factor as f32, scale_factor as f32];
+    } else if let Some(image) = images {
+      // Headless: there is no window to report its size, so use the offscreen image directly
+      let extent = image.extent();
+      io.display_size = [extent[0] as f32, extent[1] as f32];
+      io.display_framebuffer_scale = [1.0, 1.0];
+    }
     let ui = imgui.new_frame();
     {
       ui.dockspace_over_main_viewport();
     }
-    app.insert_non_send_resource(imgui);
+  }
+
+  /// Creates everything else that lives on the device: the font texture and the buffers. Runs on every new device,
+  /// after the pipeline is built, where textures registered on the old one are dropped and have to be registered
+  /// again.
+  fn init_gpu(world: &mut World) -> Resultat<()> {
+    let memory_allocator = world.resource::<ASingleton<StandardMemoryAllocator>>().clon();
+    let queue = world.resource::<ASingleton<Queue>>().clon();
+    let command_buffer_allocator = world.resource::<ASingleton<StandardCommandBufferAllocator>>().clon();
+
+    let font_texture = {
+      let mut imgui = world.non_send_resource_mut::<Context>();
+      let tex = imgui.fonts().build_rgba32_texture();
+      Self::font_texture(queue, memory_allocator.clone(), tex, command_buffer_allocator)?
+    };
+    font_texture.name::<Self>("font atlas");
+    let mut textures = world.resource_mut::<ImguiTextures>();
+    // Ids handed out before stay reserved, so a stale id never ends up showing someone else's texture
+    textures.textures.clear();
+    textures.replace(FONT_TEXTURE_ID, font_texture);
+
+    world.insert_resource(AssociatedResource::<Self, HashMap<usize, (Texture, Arc<PersistentDescriptorSet>)>>::new(
+      HashMap::new(),
+    ));
+    // Arenas are recycled once the command buffers using them are dropped, so steady frames allocate nothing new
+    let buffer_allocator = SubbufferAllocator::new(
+      memory_allocator,
+      SubbufferAllocatorCreateInfo {
+        arena_size: INITIAL_ARENA_SIZE,
+        buffer_usage: BufferUsage::VERTEX_BUFFER | BufferUsage::INDEX_BUFFER,
+        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+        ..Default::default()
+      },
+    );
+    world.insert_non_send_resource(AssociatedResource::<Self, _>::new(buffer_allocator));
+    let frames = PerFrame::<ImguiFrame>::from_default(world.resource::<FrameInFlight>());
+    world.insert_resource(AssociatedResource::<Self, _>::new(frames));
+    world.resource_mut::<ImguiStats>().arena_size = INITIAL_ARENA_SIZE;
     Ok(())
   }
 }
 
 impl ImguiPipeline {
+  fn sync_game_texture(game_target: Res<GameTarget>, mut textures: ResMut<ImguiTextures>) {
+    if let (true, Some(texture)) = (game_target.is_changed(), game_target.texture()) {
+      textures.replace(GAME_TEXTURE_ID, texture.clone());
+    }
+  }
+
+  /// Keeps one descriptor set per registered texture, rebuilding only the ones whose texture changed.
+  fn update_descriptor_sets(
+    descriptor_set_allocator: Res<ASingleton<StandardDescriptorSetAllocator>>,
+    pipeline: Res<AssociatedResource<Self, Arc<GraphicsPipeline>>>,
+    textures: Res<ImguiTextures>,
+    mut descriptor_sets: ResMut<AssociatedResource<Self, HashMap<usize, (Texture, Arc<PersistentDescriptorSet>)>>>,
+  ) -> Resultat<()> {
+    if !textures.is_changed() {
+      return Ok(());
+    }
+    descriptor_sets.retain(|id, _| textures.textures.contains_key(id));
+    let layout = pipeline.layout().set_layouts().get(0).unwrap();
+    for (id, texture) in textures.textures.iter() {
+      let up_to_date = descriptor_sets.get(id).is_some_and(|(bound, _)| {
+        Arc::ptr_eq(&bound.view, &texture.view) && Arc::ptr_eq(&bound.sampler, &texture.sampler)
+      });
+      if !up_to_date {
+        let set = PersistentDescriptorSet::new(&descriptor_set_allocator, layout.clone(), [texture.descriptor(0)], [])?;
+        debug_name::<Self>(&*set, &format!("texture {id} descriptor set"));
+        descriptor_sets.insert(*id, (texture.clone(), set));
+      }
+    }
+    Ok(())
+  }
+
   fn update(
-    memory_allocator: Res<ASingleton<StandardMemoryAllocator>>,
-    mut index_buffers: ResMut<AssociatedResource<Self, Vec<Subbuffer<[u16]>>>>,
-    mut vertex_buffers: ResMut<AssociatedResource<Self, Vec<Subbuffer<[DrawVertPod]>>>>,
-    mut draw_commands: ResMut<AssociatedResource<Self, Vec<(usize, usize, u32, u32, i32, [f32; 4])>>>,
+    buffer_allocator: NonSend<AssociatedResource<Self, SubbufferAllocator>>,
+    frame_in_flight: Res<FrameInFlight>,
+    mut frames: ResMut<AssociatedResource<Self, PerFrame<ImguiFrame>>>,
+    mut stats: ResMut<ImguiStats>,
+    mut callbacks: ResMut<ImguiCallbacks>,
     mut imgui: NonSendMut<Context>,
-  ) {
-    index_buffers.clear();
-    vertex_buffers.clear();
-    draw_commands.clear();
+    enabled_optionals: Res<EnabledOptionals>,
+  ) -> Resultat<()> {
+    // The runner waited for this slot's previous frame, so its buffers can go back to the allocator
+    let frame = frames.current_mut(&frame_in_flight);
+    let mut queued = callbacks.pending.drain(..).map(Some).collect::<Vec<_>>();
+    frame.vertices = None;
+    frame.indices = None;
+    frame.draw_commands.clear();
+    stats.uploaded_bytes = 0;
 
     let d = imgui.render();
-    let mut i = 0;
-    for dl in d.draw_lists() {
-      assert_eq!(core::mem::size_of::<DrawVertPod>(), core::mem::size_of::<DrawVert>(),);
-      assert!(core::mem::align_of::<DrawVertPod>() <= core::mem::align_of::<DrawVert>());
-      let vertices: &[DrawVertPod] =
-        unsafe { slice::from_raw_parts(dl.vtx_buffer().as_ptr().cast(), dl.vtx_buffer().len()) };
-      let vertex_buffer = Buffer::from_iter(
-        memory_allocator.clone(),
-        BufferCreateInfo {
-          usage: BufferUsage::VERTEX_BUFFER,
-          ..Default::default()
-        },
-        AllocationCreateInfo {
-          memory_type_filter: MemoryTypeFilter::HOST_RANDOM_ACCESS,
-          ..Default::default()
-        },
-        vertices.iter().cloned(),
-      )
-      .unwrap();
-
-      vertex_buffers.push(vertex_buffer);
-      let indicies = dl.idx_buffer();
-      let index_buffer = Buffer::from_iter(
-        memory_allocator.clone(),
-        BufferCreateInfo {
-          usage: BufferUsage::INDEX_BUFFER,
-          ..Default::default()
-        },
-        AllocationCreateInfo {
-          memory_type_filter: MemoryTypeFilter::HOST_RANDOM_ACCESS,
-          ..Default::default()
-        },
-        indicies.iter().copied(),
-      )
-      .unwrap();
-      index_buffers.push(index_buffer);
-
-      for cmd in dl.commands() {
-        match cmd {
-          DrawCmd::Elements { count, cmd_params } => {
-            draw_commands.push((
-              cmd_params.texture_id.id(),
-              i,
-              count as u32,
-              cmd_params.idx_offset as u32,
-              cmd_params.vtx_offset as i32,
-              cmd_params.clip_rect,
-            ));
+    frame.display_pos = d.display_pos;
+    frame.display_size = d.display_size;
+    frame.framebuffer_scale = d.framebuffer_scale;
+    let vertex_count = d.total_vtx_count as usize;
+    let index_count = d.total_idx_count as usize;
+    if vertex_count == 0 || index_count == 0 {
+      return Ok(());
+    }
+
+    let vertex_bytes = (vertex_count * size_of::<DrawVertPod>()) as DeviceSize;
+    let index_bytes = (index_count * size_of::<DrawIdx>()) as DeviceSize;
+    // Both slices have to fit in one arena, otherwise every frame would end up allocating a dedicated buffer
+    let needed = (vertex_bytes + index_bytes + 256).next_power_of_two();
+    if needed > stats.arena_size {
+      buffer_allocator.set_arena_size(needed);
+      stats.arena_size = needed;
+    }
+
+    let vertices = buffer_allocator.allocate_slice::<DrawVertPod>(vertex_count as DeviceSize)?;
+    let indices = buffer_allocator.allocate_slice::<DrawIdx>(index_count as DeviceSize)?;
+    // Usually one arena holds both, but they may land in different ones after it grew
+    debug_name::<Self>(&**vertices.buffer(), "vertex and index arena");
+    debug_name::<Self>(&**indices.buffer(), "vertex and index arena");
+    let rebase_indices = size_of::<DrawIdx>() > 2 && !enabled_optionals.features.full_draw_index_uint32;
+    {
+      let mut vertex_writer = vertices.write()?;
+      let mut index_writer = indices.write()?;
+      let (mut vertex_base, mut index_base) = (0, 0);
+      for dl in d.draw_lists() {
+        assert_eq!(size_of::<DrawVertPod>(), size_of::<DrawVert>());
+        assert!(core::mem::align_of::<DrawVertPod>() <= core::mem::align_of::<DrawVert>());
+        let dl_vertices: &[DrawVertPod] =
+          unsafe { slice::from_raw_parts(dl.vtx_buffer().as_ptr().cast(), dl.vtx_buffer().len()) };
+        let dl_indices = dl.idx_buffer();
+        vertex_writer[vertex_base..vertex_base + dl_vertices.len()].copy_from_slice(dl_vertices);
+        index_writer[index_base..index_base + dl_indices.len()].copy_from_slice(dl_indices);
+
+        for cmd in dl.commands() {
+          match cmd {
+            DrawCmd::Elements { count, cmd_params } => {
+              let first_index = index_base + cmd_params.idx_offset;
+              let mut vertex_offset = vertex_base + cmd_params.vtx_offset;
+              if rebase_indices {
+                let cmd_indices = &mut index_writer[first_index..first_index + count];
+                let base = cmd_indices.iter().copied().min().unwrap_or(0);
+                cmd_indices.iter_mut().for_each(|index| *index -= base);
+                vertex_offset += base as usize;
+              }
+              frame.draw_commands.push(ImguiDrawCommand::Elements {
+                texture_id: cmd_params.texture_id.id(),
+                index_count: count as u32,
+                first_index: first_index as u32,
+                vertex_offset: vertex_offset as i32,
+                clip_rect: cmd_params.clip_rect,
+              });
+            }
+            DrawCmd::ResetRenderState => frame.draw_commands.push(ImguiDrawCommand::ResetRenderState),
+            DrawCmd::RawCallback { callback, raw_cmd } if callback as usize == queued_callback as usize => {
+              let raw_cmd = unsafe { &*raw_cmd };
+              let callback = queued.get_mut(raw_cmd.UserCallbackData as usize).and_then(Option::take);
+              if let Some(callback) = callback {
+                frame.draw_commands.push(ImguiDrawCommand::Callback {
+                  callback,
+                  clip_rect: [raw_cmd.ClipRect.x, raw_cmd.ClipRect.y, raw_cmd.ClipRect.z, raw_cmd.ClipRect.w],
+                });
+              }
+            }
+            // Callbacks added straight through imgui_sys only get to run on the CPU, as imgui specifies
+            DrawCmd::RawCallback { callback, raw_cmd } => unsafe { callback(dl.raw(), raw_cmd) },
           }
-          DrawCmd::ResetRenderState => {}
-          DrawCmd::RawCallback { .. } => {}
         }
+        vertex_base += dl_vertices.len();
+        index_base += dl_indices.len();
       }
-      i += 1;
     }
+
+    stats.uploaded_bytes = vertex_bytes + index_bytes;
+    frame.vertices = Some(vertices);
+    frame.indices = Some(indices);
+    Ok(())
+  }
+
+  /// Binds everything imgui's draws rely on, at the start of the pass and again whenever a callback or a
+  /// `ResetRenderState` may have changed it. Descriptor sets are bound per draw.
+  fn reset_render_state(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    pipeline: &Arc<GraphicsPipeline>,
+    viewport: &Viewport,
+    frame: &ImguiFrame,
+  ) -> Resultat<()> {
+    let (Some(vertices), Some(indices)) = (&frame.vertices, &frame.indices) else {
+      return Ok(());
+    };
+    builder
+      .bind_pipeline_graphics(pipeline.clone())?
+      .set_viewport(0, smallvec![viewport.clone()])?
+      .bind_vertex_buffers(0, vertices.clone())?
+      .bind_index_buffer(indices.clone())?
+      .push_constants(
+        pipeline.layout().clone(),
+        0,
+        vs::PushConstants {
+          window_width: frame.display_size[0],
+          window_height: frame.display_size[1],
+        },
+      )?;
+    Ok(())
   }
 
   fn bind(
     mut builder: NonSendMut<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>,
-    surface: Res<ASingleton<Surface>>,
     pipeline: Res<AssociatedResource<Self, Arc<GraphicsPipeline>>>,
-    descriptor_set: Res<AssociatedResource<Self, Arc<PersistentDescriptorSet>>>,
-    index_buffers: Res<AssociatedResource<Self, Vec<Subbuffer<[u16]>>>>,
-    vertex_buffers: Res<AssociatedResource<Self, Vec<Subbuffer<[DrawVertPod]>>>>,
-    draw_commands: Res<AssociatedResource<Self, Vec<(usize, usize, u32, u32, i32, [f32; 4])>>>,
+    descriptor_sets: Res<AssociatedResource<Self, HashMap<usize, (Texture, Arc<PersistentDescriptorSet>)>>>,
+    output_format: Res<NamedSingleton<"Output", Format>>,
+    frame_in_flight: Res<FrameInFlight>,
+    mut frames: ResMut<AssociatedResource<Self, PerFrame<ImguiFrame>>>,
     viewport: Res<Singleton<Viewport>>,
-    framebuffer: Res<ANamedSingleton<"Output", ImageView>>,
   ) -> Resultat<()> {
-    let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
-    builder
-      .begin_rendering(RenderingInfo {
-        color_attachments: vec![Some(RenderingAttachmentInfo {
-          load_op: AttachmentLoadOp::Clear,
-          store_op: AttachmentStoreOp::Store,
-          clear_value: Some([0.0, 0.0, 0.1, 1.0].into()),
-          ..RenderingAttachmentInfo::image_view(framebuffer.clon())
-        })],
-        ..Default::default()
-      })
-      .unwrap()
-      .set_viewport(0, smallvec![viewport.0.clone()])
-      .unwrap();
-    for (_texture, buf, index_count, first_index, vertex_offset, clip_rect) in draw_commands.iter() {
-      builder.bind_pipeline_graphics(pipeline.clone())?
-        .bind_descriptor_sets(
-          PipelineBindPoint::Graphics,
-          pipeline.layout().clone(),
-          0,
-          descriptor_set.clone(),
-        )?;
-      builder
-        .bind_vertex_buffers(0, vertex_buffers[*buf].clone())?
-        .bind_index_buffer(index_buffers[*buf].clone())?
-        .set_scissor(
-          0,
-          smallvec![Scissor {
-            offset: [
-              (f32::max(0.0, clip_rect[0]) * window.scale_factor() as f32) as u32,
-              (f32::max(0.0, clip_rect[1]) * window.scale_factor() as f32) as u32,
-            ],
-            extent: [
-              ((clip_rect[2] - clip_rect[0]).abs().ceil() * window.scale_factor() as f32) as u32,
-              ((clip_rect[3] - clip_rect[1]).abs().ceil() * window.scale_factor() as f32) as u32,
-            ],
-          }],
-        )?
-        .push_constants(
-          pipeline.layout().clone(),
-          0,
-          vs::PushConstants {
-            window_height: window.inner_size().to_logical(window.scale_factor()).height,
-            window_width: window.inner_size().to_logical(window.scale_factor()).width,
-          },
-        )?
-        .set_viewport(0, smallvec![viewport.0.clone()])?
-        .draw_indexed(*index_count, 1, *first_index, *vertex_offset, 0)?;
+    let frame = frames.current_mut(&frame_in_flight);
+    if frame.vertices.is_none() {
+      return Ok(());
+    }
+    let draw_commands = mem::take(&mut frame.draw_commands);
+    let framebuffer_extent = [viewport.extent[0] as u32, viewport.extent[1] as u32];
+    let scissor = |clip_rect| {
+      clip_rect_to_scissor(clip_rect, frame.display_pos, frame.framebuffer_scale, framebuffer_extent)
+    };
+    Self::reset_render_state(&mut builder, &pipeline, &viewport, frame)?;
+    let mut bound_texture = None;
+    for command in draw_commands {
+      match command {
+        ImguiDrawCommand::Elements {
+          texture_id,
+          index_count,
+          first_index,
+          vertex_offset,
+          clip_rect,
+        } => {
+          // Textures unregistered since the frame was built are skipped rather than drawn with the wrong image
+          let Some((_, descriptor_set)) = descriptor_sets.get(&texture_id) else {
+            continue;
+          };
+          let Some(scissor) = scissor(clip_rect) else {
+            continue;
+          };
+          if bound_texture != Some(texture_id) {
+            builder.bind_descriptor_sets(
+              PipelineBindPoint::Graphics,
+              pipeline.layout().clone(),
+              0,
+              descriptor_set.clone(),
+            )?;
+            bound_texture = Some(texture_id);
+          }
+          builder
+            .set_scissor(0, smallvec![scissor])?
+            .draw_indexed(index_count, 1, first_index, vertex_offset, 0)?;
+        }
+        ImguiDrawCommand::ResetRenderState => {
+          Self::reset_render_state(&mut builder, &pipeline, &viewport, frame)?;
+          bound_texture = None;
+        }
+        ImguiDrawCommand::Callback { callback, clip_rect } => {
+          let Some(scissor) = scissor(clip_rect) else {
+            continue;
+          };
+          let info = ImguiCallbackInfo {
+            clip_rect,
+            scissor,
+            viewport: viewport.0.clone(),
+            output_format: **output_format,
+          };
+          builder.set_scissor(0, smallvec![info.scissor])?;
+          callback(&mut builder, &info)?;
+          Self::reset_render_state(&mut builder, &pipeline, &viewport, frame)?;
+          bound_texture = None;
+        }
+      }
     }
     Ok(())
   }
   fn handle_event(
     mut events: EventReader<WinitEvent>,
     mut imgui: NonSendMut<Context>,
-    surface: Res<ASingleton<Surface>>,
+    surface: Option<Res<ASingleton<Surface>>>,
   ) {
+    let Some(surface) = surface else {
+      return;
+    };
     let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
     for event in events.read() {
       match event.0 {
@@ -633,11 +862,93 @@ impl ImguiPipeline {
 
 impl Plugin for ImguiPipeline {
   fn build(&self, app: &mut App) {
-    ImguiPipeline::init(app).unwrap();
+    ImguiPipeline::init(app);
+    // Saves rebasing 32-bit imgui indices to stay below the 2^24 index limit devices have without this
+    app.world.resource_mut::<DeviceRequirements>().request_features(Features {
+      full_draw_index_uint32: true,
+      ..Features::empty()
+    });
+    app.add_plugins(RenderPipelinePlugin::<Self>::default());
+    app.world.resource_mut::<DeviceInitializers>().register(ImguiPipeline::init_gpu);
     app.add_systems(PreUpdate, ImguiPipeline::handle_event);
-    app.add_systems(PostUpdate, ImguiPipeline::update);
+    // Last, so the game target has already been resized for this frame
+    app.add_systems(
+      Last,
+      (
+        ImguiPipeline::sync_game_texture,
+        ImguiPipeline::update_descriptor_sets.pipe(handle_result),
+      )
+        .chain(),
+    );
+    app.add_systems(PostUpdate, ImguiPipeline::update.pipe(handle_result));
+  }
+
+  fn finish(&self, app: &mut App) {
+    ImguiPipeline::start(app);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn scissor(
+    clip_rect: [f32; 4],
+    display_pos: [f32; 2],
+    framebuffer_scale: f32,
+    framebuffer_extent: [u32; 2],
+  ) -> Option<([u32; 2], [u32; 2])> {
+    clip_rect_to_scissor(
+      clip_rect,
+      display_pos,
+      [framebuffer_scale, framebuffer_scale],
+      framebuffer_extent,
+    )
+    .map(|scissor| (scissor.offset, scissor.extent))
+  }
+
+  #[test]
+  fn scales_to_framebuffer_pixels() {
+    let rect = [10.0, 20.0, 110.0, 70.0];
+    assert_eq!(scissor(rect, [0.0, 0.0], 1.0, [800, 600]), Some(([10, 20], [100, 50])));
+    assert_eq!(scissor(rect, [0.0, 0.0], 1.5, [800, 600]), Some(([15, 30], [150, 75])));
+    assert_eq!(scissor(rect, [0.0, 0.0], 2.0, [800, 600]), Some(([20, 40], [200, 100])));
+  }
+
+  #[test]
+  fn is_relative_to_the_display_pos() {
+    let rect = [110.0, 60.0, 210.0, 160.0];
+    assert_eq!(scissor(rect, [100.0, 50.0], 1.0, [800, 600]), Some(([10, 10], [100, 100])));
+    assert_eq!(scissor(rect, [100.0, 50.0], 2.0, [800, 600]), Some(([20, 20], [200, 200])));
+    assert_eq!(scissor(rect, [-100.0, -50.0], 1.0, [800, 600]), Some(([210, 110], [100, 100])));
+  }
+
+  #[test]
+  fn grows_fractional_edges_to_whole_pixels() {
+    assert_eq!(scissor([1.0, 1.0, 2.0, 2.0], [0.0, 0.0], 1.5, [800, 600]), Some(([1, 1], [2, 2])));
+    assert_eq!(scissor([0.3, 0.7, 10.2, 10.9], [0.0, 0.0], 1.0, [800, 600]), Some(([0, 0], [11, 11])));
+    assert_eq!(scissor([5.5, 5.5, 5.6, 5.6], [0.0, 0.0], 1.0, [800, 600]), Some(([5, 5], [1, 1])));
+  }
+
+  #[test]
+  fn clamps_to_the_framebuffer() {
+    let rect = [-50.0, -50.0, 900.0, 700.0];
+    assert_eq!(scissor(rect, [0.0, 0.0], 1.0, [800, 600]), Some(([0, 0], [800, 600])));
+    assert_eq!(scissor(rect, [0.0, 0.0], 2.0, [800, 600]), Some(([0, 0], [800, 600])));
+    assert_eq!(scissor([700.0, 500.0, 900.0, 700.0], [0.0, 0.0], 1.0, [800, 600]), Some(([700, 500], [100, 100])));
+  }
 
-    let system_id = app.world.register_system(ImguiPipeline::bind.pipe(handle_result));
-    app.world.resource_mut::<PipelineRunner>().order.push(system_id);
+  #[test]
+  fn nothing_visible_is_none() {
+    // Empty and inverted
+    assert_eq!(scissor([10.0, 10.0, 10.0, 20.0], [0.0, 0.0], 1.0, [800, 600]), None);
+    assert_eq!(scissor([10.0, 20.0, 20.0, 10.0], [0.0, 0.0], 1.0, [800, 600]), None);
+    // Entirely off screen on either side
+    assert_eq!(scissor([900.0, 0.0, 1000.0, 100.0], [0.0, 0.0], 1.0, [800, 600]), None);
+    assert_eq!(scissor([0.0, 600.0, 100.0, 700.0], [0.0, 0.0], 1.0, [800, 600]), None);
+    assert_eq!(scissor([-100.0, -100.0, -10.0, -10.0], [0.0, 0.0], 1.0, [800, 600]), None);
+    assert_eq!(scissor([10.0, 10.0, 90.0, 90.0], [100.0, 100.0], 1.0, [800, 600]), None);
+    // Only on screen before scaling
+    assert_eq!(scissor([500.0, 0.0, 700.0, 100.0], [0.0, 0.0], 2.0, [800, 600]), None);
   }
 }
@@ -1,106 +1,258 @@
-use crate::engine::{ASingleton, PipelineRunner, Singleton};
+use crate::engine::device::{
+  self, DeviceCandidate, DeviceRequirements, DeviceSelector, EnabledOptionals, SelectedDevice,
+};
+use crate::engine::error::EngineError;
+use crate::engine::log::{EngineLog, LogLevel, LogSource};
+use crate::engine::pipeline_cache;
+use crate::engine::render_graph::RenderGraph;
+use crate::engine::settings::{EngineSettings, FullscreenMode, SwapchainSettings, WindowSettings};
+use crate::engine::{debug_name, ANamedSingleton, ASingleton, NamedSingleton, Resultat, Singleton};
 use bevy_app::App;
-use std::error::Error;
+use bevy_ecs::prelude::*;
 use std::sync::Arc;
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::descriptor_set::allocator::{StandardDescriptorSetAllocator, StandardDescriptorSetAllocatorCreateInfo};
-use vulkano::device::physical::PhysicalDeviceType;
-use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo, QueueFlags};
-use vulkano::image::{Image, ImageUsage};
-use vulkano::instance::{Instance, InstanceCreateInfo};
-use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::instance::debug::{
+  DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger, DebugUtilsMessengerCallback,
+  DebugUtilsMessengerCreateInfo,
+};
+use vulkano::instance::{Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
 use vulkano::pipeline::graphics::viewport::Viewport;
-use vulkano::swapchain::{PresentMode, Surface, Swapchain, SwapchainCreateInfo};
+use vulkano::swapchain::{ColorSpace, PresentMode, Surface, Swapchain, SwapchainCreateInfo};
 use vulkano::VulkanLibrary;
+use winit::dpi::LogicalSize;
 use winit::event_loop::EventLoop;
-use winit::window::{Window, WindowBuilder};
+use winit::window::{Fullscreen, Window, WindowBuilder};
+
+/// Checks API usage in debug mode, see [`EngineSettings::debug`].
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Format of the offscreen image used in place of the swapchain when running headless.
+pub const HEADLESS_FORMAT: Format = Format::R8G8B8A8_SRGB;
 
 pub struct RuminativeInternals;
 
 impl RuminativeInternals {
-  fn instance(event_loop: &EventLoop<()>) -> Result<Arc<Instance>, Box<dyn Error>> {
+  /// With a `debug_log`, also enables the validation layer and debug utils where installed, so their messages can be
+  /// routed into the log by [`Self::debug_messenger`].
+  fn instance(event_loop: Option<&EventLoop<()>>, debug_log: Option<&EngineLog>) -> Resultat<Arc<Instance>> {
     let library = VulkanLibrary::new()?;
-    let required_extensions = Surface::required_extensions(event_loop);
+    let mut enabled_extensions = event_loop
+      .map(|event_loop| Surface::required_extensions(event_loop))
+      .unwrap_or(InstanceExtensions::empty());
+    let mut enabled_layers = vec![];
+    if let Some(log) = debug_log {
+      if library.layer_properties()?.any(|layer| layer.name() == VALIDATION_LAYER) {
+        enabled_layers.push(VALIDATION_LAYER.to_string());
+      } else {
+        log.push(LogLevel::Warning, LogSource::Engine, format!("{VALIDATION_LAYER} is not installed"));
+      }
+      if library.supported_extensions().ext_debug_utils {
+        enabled_extensions.ext_debug_utils = true;
+      } else {
+        let message = "ext_debug_utils is not supported, validation messages are lost";
+        log.push(LogLevel::Warning, LogSource::Engine, message);
+      }
+    }
+
+    // Lets portability drivers (MoltenVK, some software rasterizers) show up when running headless
+    let mut flags = InstanceCreateFlags::empty();
+    if library.supported_extensions().khr_portability_enumeration {
+      enabled_extensions.khr_portability_enumeration = true;
+      flags |= InstanceCreateFlags::ENUMERATE_PORTABILITY;
+    }
 
     let instance = Instance::new(
       library,
       InstanceCreateInfo {
-        enabled_extensions: required_extensions,
+        flags,
+        enabled_extensions,
+        enabled_layers,
         ..Default::default()
       },
     )?;
     Ok(instance)
   }
-  fn device_surface_and_queue(
-    event_loop: &EventLoop<()>,
-    instance: Arc<Instance>,
-  ) -> Result<(Arc<Device>, Arc<Surface>, Arc<Queue>), Box<dyn Error>> {
-    let window = Arc::new(WindowBuilder::new().build(&event_loop)?);
-    let surface = Surface::from_window(instance.clone(), window.clone())?;
-
-    let device_extensions = DeviceExtensions {
-      khr_swapchain: true,
-      ..DeviceExtensions::empty()
+  /// Forwards validation and driver messages into `log`, for as long as the messenger is alive.
+  fn debug_messenger(instance: Arc<Instance>, log: EngineLog) -> Resultat<DebugUtilsMessenger> {
+    // Safety: the callback only locks the log and never calls into Vulkan
+    let callback = unsafe {
+      DebugUtilsMessengerCallback::new(move |severity, ty, data| {
+        let message = match data.message_id_name {
+          Some(id) => format!("{id}: {}", data.message),
+          None => data.message.to_string(),
+        };
+        log.push(severity.into(), ty.into(), message);
+      })
     };
-
-    let (physical_device, queue_family_index) = instance
-      .enumerate_physical_devices()?
-      .filter(|p| p.supported_extensions().contains(&device_extensions))
-      .filter_map(|p| {
-        p.queue_family_properties()
-          .iter()
-          .enumerate()
-          .position(|(i, q)| {
-            q.queue_flags.intersects(QueueFlags::GRAPHICS) && p.surface_support(i as u32, &surface).unwrap_or(false)
-          })
-          .map(|i| (p, i as u32))
+    Ok(DebugUtilsMessenger::new(
+      instance,
+      DebugUtilsMessengerCreateInfo {
+        // Info is mostly loader and layer chatter, which would push real errors out of the log
+        message_severity: DebugUtilsMessageSeverity::ERROR | DebugUtilsMessageSeverity::WARNING,
+        message_type: DebugUtilsMessageType::GENERAL
+          | DebugUtilsMessageType::VALIDATION
+          | DebugUtilsMessageType::PERFORMANCE,
+        ..DebugUtilsMessengerCreateInfo::user_callback(callback)
+      },
+    )?)
+  }
+  /// Creates the instance for `settings`, with a debug messenger kept in the world when in debug mode.
+  fn instance_in_app(
+    event_loop: Option<&EventLoop<()>>,
+    settings: &EngineSettings,
+    world: &mut World,
+  ) -> Resultat<Arc<Instance>> {
+    let log = world.resource::<EngineLog>().clone();
+    let instance = Self::instance(event_loop, settings.debug.then_some(&log))?;
+    if instance.enabled_extensions().ext_debug_utils {
+      let messenger = Self::debug_messenger(instance.clone(), log)?;
+      world.insert_resource(Singleton(messenger));
+    }
+    Ok(instance)
+  }
+  fn physical_device(
+    instance: &Arc<Instance>,
+    surface: Option<&Surface>,
+    requirements: &DeviceRequirements,
+    selector: Option<&DeviceSelector>,
+  ) -> Resultat<SelectedDevice> {
+    let candidates = device::candidates(instance, surface, requirements, selector)?;
+    candidates
+      .iter()
+      .find(|candidate| candidate.rejection.is_none())
+      .map(|candidate| SelectedDevice {
+        index: candidate.index,
+        physical_device: candidate.physical_device.clone(),
+        queue_family_index: candidate.queue_family_index.unwrap(),
       })
-      .min_by_key(|(p, _)| match p.properties().device_type {
-        PhysicalDeviceType::IntegratedGpu => 1,
-        PhysicalDeviceType::DiscreteGpu => 0,
-        PhysicalDeviceType::VirtualGpu => 2,
-        PhysicalDeviceType::Cpu => 3,
-        PhysicalDeviceType::Other => 4,
-        _ => 5,
+      .ok_or_else(|| {
+        let candidates = candidates.iter().map(ToString::to_string).collect::<Vec<_>>();
+        format!("no usable device among [{}]", candidates.join("; ")).into()
       })
-      .ok_or("Can't find physical device")?;
-
-    println!(
-      "Using device: {} {:?}",
-      physical_device.properties().device_name,
-      physical_device.properties().device_type
-    );
-
+  }
+  /// The [`DeviceSelector`] from [`DEVICE_ENV_VAR`](device::DEVICE_ENV_VAR), or else from the settings.
+  fn device_selector(settings: &EngineSettings) -> Option<DeviceSelector> {
+    DeviceSelector::from_env().or_else(|| settings.device.clone())
+  }
+  /// Every device the engine could run on headless with `requirements`, with the reason it would or wouldn't be
+  /// picked.
+  pub fn list_devices(settings: &EngineSettings, requirements: &DeviceRequirements) -> Resultat<Vec<DeviceCandidate>> {
+    let instance = Self::instance(None, None)?;
+    let selector = Self::device_selector(settings);
+    device::candidates(&instance, None, requirements, selector.as_ref())
+  }
+  fn device_and_queue(
+    instance: Arc<Instance>,
+    surface: Option<&Surface>,
+    requirements: &DeviceRequirements,
+    selector: Option<&DeviceSelector>,
+  ) -> Resultat<(SelectedDevice, Arc<Device>, Arc<Queue>)> {
+    // Only failing to pick a device is a selection error, failing to create it on the picked one is a Vulkan error
+    let selected_device = Self::physical_device(&instance, surface, requirements, selector)
+      .map_err(|e| EngineError::DeviceSelection(e.into()))?;
+    let (enabled_extensions, enabled_features) = requirements.enabled_on(&selected_device.physical_device);
     let (device, mut queues) = Device::new(
-      physical_device,
+      selected_device.physical_device.clone(),
       DeviceCreateInfo {
-        enabled_extensions: device_extensions,
+        enabled_extensions,
         queue_create_infos: vec![QueueCreateInfo {
-          queue_family_index,
+          queue_family_index: selected_device.queue_family_index,
           ..Default::default()
         }],
-        enabled_features: Features {
-          dynamic_rendering: true,
-          ..Features::empty()
-        },
+        enabled_features,
         ..Default::default()
       },
     )?;
 
     let queue = queues.next().ok_or("No queue")?;
-    Ok((device, surface, queue))
+    Ok((selected_device, device, queue))
+  }
+  fn surface(
+    event_loop: &EventLoop<()>,
+    instance: Arc<Instance>,
+    settings: &WindowSettings,
+  ) -> Resultat<Arc<Surface>> {
+    let fullscreen = match settings.fullscreen {
+      FullscreenMode::Windowed => None,
+      FullscreenMode::Borderless => Some(Fullscreen::Borderless(None)),
+      FullscreenMode::Exclusive => Some(
+        event_loop
+          .primary_monitor()
+          .and_then(|monitor| {
+            monitor
+              .video_modes()
+              .max_by_key(|mode| (mode.size().width * mode.size().height, mode.refresh_rate_millihertz()))
+          })
+          .map_or(Fullscreen::Borderless(None), Fullscreen::Exclusive),
+      ),
+    };
+    let window = WindowBuilder::new()
+      .with_title(settings.title.clone())
+      .with_inner_size(LogicalSize::new(settings.size[0], settings.size[1]))
+      .with_resizable(settings.resizable)
+      .with_fullscreen(fullscreen)
+      .build(event_loop)?;
+    Ok(Surface::from_window(instance, Arc::new(window))?)
+  }
+  /// The format and color space `settings` ask for, or the best the surface supports. Also checked whenever the
+  /// swapchain is recreated, since what a surface supports can change with the monitor it is on.
+  pub fn surface_format(
+    physical_device: &PhysicalDevice,
+    surface: &Surface,
+    settings: &SwapchainSettings,
+  ) -> Resultat<(Format, ColorSpace)> {
+    let surface_formats = physical_device.surface_formats(surface, Default::default())?;
+    // Without a preference, or when it isn't available, prefer 8-bit per channel formats over wider ones
+    let heuristic = || {
+      *surface_formats
+        .iter()
+        .max_by_key(|(format, _)| {
+          let s: u8 = format.components().into_iter().take(3).sum();
+          if s == 8 * 3 { 2 } else if s > 8 * 3 { 1 } else { 0 }
+        })
+        .unwrap()
+    };
+    let has_preference = settings.format.is_some() || settings.color_space.is_some();
+    let (image_format, image_color_space) = surface_formats
+      .iter()
+      .copied()
+      .find(|(format, color_space)| {
+        has_preference
+          && settings.format.map_or(true, |preference| Format::from(preference) == *format)
+          && settings.color_space.map_or(true, |preference| ColorSpace::from(preference) == *color_space)
+      })
+      .unwrap_or_else(heuristic);
+    Ok((image_format, image_color_space))
   }
   fn swapchain_and_images(
     device: Arc<Device>,
     surface: Arc<Surface>,
-  ) -> Result<(Arc<Swapchain>, Vec<Arc<Image>>), Box<dyn Error>> {
-    let surface_capabilities = device
-      .physical_device()
-      .surface_capabilities(&surface, Default::default())?;
-    let image_format = *device.physical_device().surface_formats(&surface, Default::default())?.iter().map(|(format, _)| {
-      let s : u8 = format.components().into_iter().take(3).sum();
-      (format, if s == 8*3 { 2 } else if s > 8*3 { 1 } else { 0 })
-    }).max_by(|a, b| a.1.cmp(&b.1)).unwrap().0;
+    settings: &SwapchainSettings,
+  ) -> Resultat<(Arc<Swapchain>, Vec<Arc<Image>>)> {
+    let physical_device = device.physical_device();
+    let surface_capabilities = physical_device.surface_capabilities(&surface, Default::default())?;
+    let (image_format, image_color_space) = Self::surface_format(physical_device, &surface, settings)?;
+    let supported_present_modes = physical_device
+      .surface_present_modes(&surface, Default::default())?
+      .collect::<Vec<_>>();
+    let present_mode = settings
+      .present_modes
+      .iter()
+      .map(|&mode| PresentMode::from(mode))
+      .find(|mode| supported_present_modes.contains(mode))
+      .unwrap_or(PresentMode::Fifo);
+    let max_image_count = surface_capabilities.max_image_count.unwrap_or(u32::MAX);
+    let min_image_count = settings
+      .image_count
+      .unwrap_or(surface_capabilities.min_image_count)
+      .clamp(surface_capabilities.min_image_count, max_image_count);
     let window = surface
       .object()
       .ok_or("No object")?
@@ -110,11 +262,12 @@ impl RuminativeInternals {
       device,
       surface.clone(),
       SwapchainCreateInfo {
-        min_image_count: surface_capabilities.min_image_count,
+        min_image_count,
         image_format,
+        image_color_space,
         image_extent: window.inner_size().into(),
         image_usage: ImageUsage::COLOR_ATTACHMENT,
-        present_mode: PresentMode::Fifo,
+        present_mode,
         composite_alpha: surface_capabilities
           .supported_composite_alpha
           .into_iter()
@@ -124,32 +277,182 @@ impl RuminativeInternals {
       },
     )?)
   }
-  pub fn new_in_app(event_loop: &EventLoop<()>, world: &mut App) -> Result<(), Box<dyn Error>> {
-    let instance = Self::instance(event_loop)?;
-    let (device, surface, queue) = Self::device_surface_and_queue(&event_loop, instance)?;
-    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
-    let (swapchain, images) = Self::swapchain_and_images(device.clone(), surface.clone())?;
-
-    world.init_resource::<PipelineRunner>();
-    // descriptor_set_layout: Res<ANamedSingleton<"Sampler", DescriptorSetLayout>>,
-    // descriptor_set_allocator: Res<ASingleton<StandardDescriptorSetAllocator>>,
-    // command_buffer_allocator: Res<ASingleton<StandardCommandBufferAllocator>>,
-
-    // let descriptor_set_layout = PersistentDescriptorSet::new();
-
+  fn offscreen_image(memory_allocator: Arc<StandardMemoryAllocator>, extent: [u32; 2]) -> Resultat<Arc<Image>> {
+    Ok(Image::new(
+      memory_allocator,
+      ImageCreateInfo {
+        image_type: ImageType::Dim2d,
+        format: HEADLESS_FORMAT,
+        extent: [extent[0], extent[1], 1],
+        usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC | ImageUsage::SAMPLED,
+        ..Default::default()
+      },
+      AllocationCreateInfo {
+        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+        ..Default::default()
+      },
+    )?)
+  }
+  /// Resources that outlive the device and are filled in by plugins before it exists: the render graph, the
+  /// device requirements and the initializers run on every new device.
+  pub fn insert_persistent(world: &mut App) {
+    let mut render_graph = RenderGraph::default();
+    render_graph.declare_attachment("Output", Some([0.0, 0.0, 0.1, 1.0]));
+    world.insert_resource(render_graph);
+    world.init_resource::<DeviceRequirements>();
+    world.init_resource::<DeviceInitializers>();
+  }
+  fn insert_common(
+    world: &mut World,
+    selected_device: SelectedDevice,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    output_format: Format,
+    images: Vec<Arc<Image>>,
+  ) -> Resultat<()> {
+    let pipeline_cache_path = world.resource::<EngineSettings>().pipeline_cache.clone();
+    let pipeline_cache = pipeline_cache::load(device.clone(), pipeline_cache_path.as_deref())?;
+    debug_name::<Self>(&*pipeline_cache, "pipeline cache");
     let descriptor_set_allocator =
       StandardDescriptorSetAllocator::new(device.clone(), StandardDescriptorSetAllocatorCreateInfo::default());
     let command_buffer_allocator = StandardCommandBufferAllocator::new(device.clone(), Default::default());
 
     world.insert_resource(ASingleton(Arc::new(descriptor_set_allocator)));
     world.insert_resource(ASingleton(Arc::new(command_buffer_allocator)));
+    world.insert_resource(ASingleton(pipeline_cache));
+    let enabled_optionals = EnabledOptionals::new(world.resource::<DeviceRequirements>(), &device);
+    world.insert_resource(enabled_optionals);
+    world.insert_resource(selected_device);
     world.insert_resource(ASingleton(device));
-    world.insert_resource(ASingleton(surface));
     world.insert_resource(ASingleton(queue));
     world.insert_resource(ASingleton(memory_allocator));
-    world.insert_resource(ASingleton(swapchain));
+    world.insert_resource(NamedSingleton::<"Output", _>(output_format));
     world.insert_resource(Singleton(Viewport::default()));
+    for (i, image) in images.iter().enumerate() {
+      debug_name::<Self>(&**image, &format!("output image {i}"));
+    }
     world.insert_resource(Singleton(images));
     Ok(())
   }
+  pub fn new_in_app(
+    event_loop: &EventLoop<()>,
+    settings: &EngineSettings,
+    world: &mut App,
+  ) -> Resultat<()> {
+    let instance = Self::instance_in_app(Some(event_loop), settings, &mut world.world)?;
+    let surface =
+      Self::surface(event_loop, instance.clone(), &settings.window).map_err(|e| EngineError::Surface(e.into()))?;
+    let selector = Self::device_selector(settings);
+    let requirements = world.world.resource::<DeviceRequirements>();
+    let (selected_device, device, queue) =
+      Self::device_and_queue(instance, Some(&surface), requirements, selector.as_ref())?;
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+    let (swapchain, images) = Self::swapchain_and_images(device.clone(), surface.clone(), &settings.swapchain)
+      .map_err(|e| EngineError::Swapchain(e.into()))?;
+
+    let output_format = swapchain.image_format();
+    Self::insert_common(&mut world.world, selected_device, device, queue, memory_allocator, output_format, images)?;
+    world.insert_resource(ASingleton(surface));
+    world.insert_resource(ASingleton(swapchain));
+    Ok(())
+  }
+  /// Same as [`Self::new_in_app`], but without a window: frames are rendered into a single offscreen image of the
+  /// given extent, which takes the place of the swapchain images.
+  pub fn new_headless_in_app(extent: [u32; 2], settings: &EngineSettings, world: &mut App) -> Resultat<()> {
+    let instance = Self::instance_in_app(None, settings, &mut world.world)?;
+    let selector = Self::device_selector(settings);
+    let requirements = world.world.resource::<DeviceRequirements>();
+    let (selected_device, device, queue) = Self::device_and_queue(instance, None, requirements, selector.as_ref())?;
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+    let image = Self::offscreen_image(memory_allocator.clone(), extent)?;
+
+    let images = vec![image];
+    Self::insert_common(&mut world.world, selected_device, device, queue, memory_allocator, HEADLESS_FORMAT, images)?;
+    Ok(())
+  }
+  /// Replaces the device and everything created from it, e.g. after the device was lost. The instance, window and
+  /// surface are kept, the swapchain or offscreen image is recreated with the same settings, then every
+  /// [`DeviceInitializers`] entry runs again so plugins can rebuild their pipelines and buffers.
+  ///
+  /// When the old swapchain is still in use or no device can be made, the world is left as it was. Once the old
+  /// swapchain is released there's no going back, so if the new one can't be created the window is left without one
+  /// and calling this again retries.
+  pub fn recreate_device(world: &mut World) -> Resultat<()> {
+    let surface = world.get_resource::<ASingleton<Surface>>().map(ASingleton::clon);
+    let instance = world.resource::<ASingleton<Device>>().physical_device().instance().clone();
+    // Only one swapchain may be live per window, so the old one has to be gone before the new one is created. Its
+    // images hold on to it too, and so does the last frame's output view, which the next frame sets again anyway.
+    world.remove_resource::<ANamedSingleton<"Output", ImageView>>();
+    world.resource_mut::<RenderGraph>().remove_image("Output");
+    let old_swapchain = world.remove_resource::<ASingleton<Swapchain>>();
+    let old_images = world.remove_resource::<Singleton<Vec<Arc<Image>>>>();
+    let restore = |world: &mut World, swapchain: Option<ASingleton<Swapchain>>, images: Option<Singleton<_>>| {
+      if let Some(swapchain) = swapchain {
+        world.insert_resource(swapchain);
+      }
+      if let Some(images) = images {
+        world.insert_resource::<Singleton<Vec<Arc<Image>>>>(images);
+      }
+    };
+    if let (Some(swapchain), Some(images)) = (&old_swapchain, &old_images) {
+      // Every swapchain image holds the swapchain once
+      let in_use = Arc::strong_count(&swapchain.0) > 1 + images.len()
+        || images.iter().any(|image| Arc::strong_count(image) > 1);
+      if in_use {
+        restore(world, old_swapchain, old_images);
+        return Err(EngineError::Swapchain("the old swapchain is still in use, the window can't get a new one".into()));
+      }
+    }
+
+    let selector = Self::device_selector(world.resource::<EngineSettings>());
+    let requirements = world.resource::<DeviceRequirements>();
+    let (selected_device, device, queue) =
+      match Self::device_and_queue(instance, surface.as_deref(), requirements, selector.as_ref()) {
+        Ok(created) => created,
+        Err(e) => {
+          restore(world, old_swapchain, old_images);
+          return Err(e);
+        }
+      };
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+    match surface {
+      Some(surface) => {
+        drop((old_swapchain, old_images));
+        let settings = world.resource::<EngineSettings>().swapchain.clone();
+        let (swapchain, images) = Self::swapchain_and_images(device.clone(), surface, &settings)
+          .map_err(|e| EngineError::Swapchain(e.into()))?;
+        let output_format = swapchain.image_format();
+        Self::insert_common(world, selected_device, device, queue, memory_allocator, output_format, images)?;
+        world.insert_resource(ASingleton(swapchain));
+      }
+      None => {
+        let extent = old_images.ok_or("there's no offscreen image to take the size of")?[0].extent();
+        let image = Self::offscreen_image(memory_allocator.clone(), [extent[0], extent[1]])?;
+        Self::insert_common(world, selected_device, device, queue, memory_allocator, HEADLESS_FORMAT, vec![image])?;
+      }
+    }
+
+    Self::run_device_initializers(world)
+  }
+  /// Runs every [`DeviceInitializers`] entry in the order they were registered.
+  pub fn run_device_initializers(world: &mut World) -> Resultat<()> {
+    let initializers = world.resource::<DeviceInitializers>().0.clone();
+    for initializer in initializers {
+      initializer(world)?;
+    }
+    Ok(())
+  }
+}
+
+/// Functions that create a plugin's device-dependent resources, such as its pipeline. Plugins register theirs while
+/// building, the engine runs them once the device is created in `Plugin::finish` and again from
+/// [`RuminativeInternals::recreate_device`].
+#[derive(Resource, Default)]
+pub struct DeviceInitializers(Vec<fn(&mut World) -> Resultat<()>>);
+
+impl DeviceInitializers {
+  pub fn register(&mut self, initializer: fn(&mut World) -> Resultat<()>) {
+    self.0.push(initializer);
+  }
 }
@@ -0,0 +1,195 @@
+use bevy_ecs::prelude::*;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use vulkano::instance::debug::{DebugUtilsMessageSeverity, DebugUtilsMessageType};
+
+/// How many entries [`EngineLog`] keeps before dropping the oldest ones. Counts keep going regardless.
+const CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+  Verbose,
+  Info,
+  Warning,
+  Error,
+}
+
+impl From<DebugUtilsMessageSeverity> for LogLevel {
+  fn from(severity: DebugUtilsMessageSeverity) -> Self {
+    if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+      LogLevel::Error
+    } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+      LogLevel::Warning
+    } else if severity.intersects(DebugUtilsMessageSeverity::INFO) {
+      LogLevel::Info
+    } else {
+      LogLevel::Verbose
+    }
+  }
+}
+
+/// Where a [`LogEntry`] came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogSource {
+  /// Errors reported by the engine's systems and runners.
+  Engine,
+  /// The validation layer complaining about API misuse.
+  Validation,
+  /// Other driver messages, such as performance warnings.
+  Driver,
+}
+
+impl From<DebugUtilsMessageType> for LogSource {
+  fn from(ty: DebugUtilsMessageType) -> Self {
+    if ty.intersects(DebugUtilsMessageType::VALIDATION) {
+      LogSource::Validation
+    } else {
+      LogSource::Driver
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+  pub level: LogLevel,
+  pub source: LogSource,
+  pub message: String,
+}
+
+impl fmt::Display for LogEntry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "[{:?}] {:?}: {}", self.source, self.level, self.message)
+  }
+}
+
+#[derive(Default)]
+struct LogState {
+  entries: VecDeque<LogEntry>,
+  errors: usize,
+  warnings: usize,
+  validation_errors: usize,
+}
+
+/// Engine errors and, in debug mode, validation layer messages. Shared with the debug messenger, which logs from
+/// whatever thread the driver calls it on, so clones all point at the same log.
+#[derive(Resource, Clone, Default)]
+pub struct EngineLog {
+  state: Arc<Mutex<LogState>>,
+}
+
+impl EngineLog {
+  /// Adds an entry, also printing warnings and errors to stderr.
+  pub fn push(&self, level: LogLevel, source: LogSource, message: impl Into<String>) {
+    let entry = LogEntry {
+      level,
+      source,
+      message: message.into(),
+    };
+    if level >= LogLevel::Warning {
+      eprintln!("{entry}");
+    }
+    let mut state = self.state();
+    match level {
+      LogLevel::Error if source == LogSource::Validation => {
+        state.errors += 1;
+        state.validation_errors += 1;
+      }
+      LogLevel::Error => state.errors += 1,
+      LogLevel::Warning => state.warnings += 1,
+      _ => {}
+    }
+    if state.entries.len() == CAPACITY {
+      state.entries.pop_front();
+    }
+    state.entries.push_back(entry);
+  }
+
+  /// The latest entries, oldest first.
+  pub fn entries(&self) -> Vec<LogEntry> {
+    self.state().entries.iter().cloned().collect()
+  }
+
+  pub fn errors(&self) -> usize {
+    self.state().errors
+  }
+
+  pub fn warnings(&self) -> usize {
+    self.state().warnings
+  }
+
+  /// Errors reported by the validation layer since the start, e.g. to assert there were none after a test run.
+  pub fn validation_errors(&self) -> usize {
+    self.state().validation_errors
+  }
+
+  /// Forgets the entries but keeps counting, so a test still sees errors logged before.
+  pub fn clear(&self) {
+    self.state().entries.clear();
+  }
+
+  fn state(&self) -> MutexGuard<'_, LogState> {
+    // A panic while holding the lock can't leave the log in a state worth refusing to read
+    self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_by_level_and_source() {
+    let log = EngineLog::default();
+    log.push(LogLevel::Error, LogSource::Engine, "engine error");
+    log.push(LogLevel::Error, LogSource::Validation, "validation error");
+    log.push(LogLevel::Error, LogSource::Driver, "driver error");
+    log.push(LogLevel::Warning, LogSource::Validation, "validation warning");
+    log.push(LogLevel::Warning, LogSource::Engine, "engine warning");
+    log.push(LogLevel::Info, LogSource::Validation, "validation info");
+    log.push(LogLevel::Verbose, LogSource::Driver, "driver chatter");
+    assert_eq!(log.errors(), 3);
+    assert_eq!(log.warnings(), 2);
+    assert_eq!(log.validation_errors(), 1);
+    assert_eq!(log.entries().len(), 7);
+  }
+
+  #[test]
+  fn clones_share_the_log() {
+    let log = EngineLog::default();
+    log.clone().push(LogLevel::Error, LogSource::Validation, "from the messenger");
+    assert_eq!(log.validation_errors(), 1);
+    assert_eq!(log.entries()[0].message, "from the messenger");
+  }
+
+  #[test]
+  fn drops_the_oldest_entries_past_capacity() {
+    let log = EngineLog::default();
+    for i in 0..CAPACITY + 5 {
+      log.push(LogLevel::Info, LogSource::Driver, i.to_string());
+    }
+    let entries = log.entries();
+    assert_eq!(entries.len(), CAPACITY);
+    assert_eq!(entries[0].message, "5");
+    assert_eq!(entries[CAPACITY - 1].message, (CAPACITY + 4).to_string());
+  }
+
+  #[test]
+  fn keeps_counting_past_capacity_and_clear() {
+    let log = EngineLog::default();
+    log.push(LogLevel::Error, LogSource::Validation, "first");
+    for _ in 0..CAPACITY {
+      log.push(LogLevel::Verbose, LogSource::Driver, "noise");
+    }
+    assert!(log.entries().iter().all(|entry| entry.level == LogLevel::Verbose));
+    assert_eq!((log.errors(), log.validation_errors()), (1, 1));
+
+    log.push(LogLevel::Warning, LogSource::Engine, "second");
+    log.clear();
+    assert!(log.entries().is_empty());
+    assert_eq!((log.errors(), log.warnings(), log.validation_errors()), (1, 1, 1));
+    log.push(LogLevel::Error, LogSource::Engine, "third");
+    assert_eq!(log.entries().len(), 1);
+    assert_eq!(log.errors(), 2);
+  }
+}
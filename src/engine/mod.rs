@@ -1,16 +1,31 @@
 use bevy_derive::*;
 use bevy_ecs::prelude::*;
-use bevy_ecs::system::{SystemId};
-use smallvec::SmallVec;
-use std::error::Error;
+use crate::engine::error::{EngineError, EngineErrorEvent};
+use crate::engine::log::{EngineLog, LogLevel, LogSource};
+use std::any::type_name;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use vulkano::device::DeviceOwned;
+use vulkano::format::Format;
+use vulkano::VulkanObject;
 use winit::event::{Event, VirtualKeyCode};
 
+pub mod camera;
+pub mod device;
+pub mod error;
+pub mod frames;
+pub mod game_target;
 pub mod imgui_pipeline;
+pub mod log;
+pub mod pipeline_cache;
 pub mod rumigui_pipeline;
 pub mod tilemap;
-pub mod barrier_pipeline;
+pub mod tilemap_pipeline;
+pub mod render_graph;
+pub mod render_pipeline;
+pub mod settings;
+pub mod shader_reload;
+pub mod texture;
 
 pub mod engine;
 pub mod internals;
@@ -18,14 +33,29 @@ pub mod internals;
 #[derive(Event)]
 pub struct WinitEvent(Event<'static, ()>);
 
-pub type Resultat<T> = Result<T, Box<dyn Error>>;
+pub type Resultat<T> = Result<T, EngineError>;
 
-pub fn handle_result(r: In<Resultat<()>>) {
+pub fn handle_result(r: In<Resultat<()>>, log: Res<EngineLog>, mut errors: EventWriter<EngineErrorEvent>) {
   if let Err(e) = r.0 {
-    eprintln!("Error: {}", e)
+    log.push(LogLevel::Error, LogSource::Engine, e.to_string());
+    errors.send(EngineErrorEvent(e));
   }
 }
 
+/// Names `object` after its owner `P` and what it is for, e.g. "ImguiPipeline font atlas image", so it can be told
+/// apart in validation messages and GPU captures. Does nothing unless debug utils are enabled, see
+/// [`EngineSettings::debug`](settings::EngineSettings::debug).
+pub fn debug_name<P>(object: &(impl VulkanObject + DeviceOwned), purpose: &str) {
+  let device = object.device();
+  if !device.physical_device().instance().enabled_extensions().ext_debug_utils {
+    return;
+  }
+  let owner = type_name::<P>().split('<').next().unwrap_or_default();
+  let owner = owner.rsplit("::").next().unwrap_or(owner);
+  // Only a debugging aid, so a name that fails to stick is not worth an error
+  let _ = device.set_debug_utils_object_name(object, Some(&format!("{owner} {purpose}")));
+}
+
 #[derive(Resource, Deref, DerefMut)]
 pub struct AssociatedResource<P, T> {
   #[deref]
@@ -42,11 +72,6 @@ impl<P, T> AssociatedResource<P, T> {
   }
 }
 
-#[derive(Resource, Default)]
-pub struct PipelineRunner {
-  pub order: SmallVec<[SystemId; 5]>,
-}
-
 #[derive(Resource, Deref, DerefMut)]
 pub struct Singleton<T>(pub T);
 
@@ -71,6 +96,7 @@ impl<const N: &'static str, T> ANamedSingleton<N, T> {
   }
 }
 
+/// Where the game is shown on screen, in imgui's logical coordinates. Kept up to date by the editor's "Game" panel.
 #[derive(Default, Resource)]
 pub struct GameViewport {
   pub pos: [f32; 2],
@@ -78,4 +104,18 @@ pub struct GameViewport {
 }
 
 #[derive(Event)]
-pub struct KeyPressed(pub VirtualKeyCode);
\ No newline at end of file
+pub struct KeyPressed(pub VirtualKeyCode);
+
+/// Sent after the runner replaced a lost device. The engine's own pipelines are already rebuilt by then, but
+/// buffers, images and descriptor sets the game created on the old device are gone and have to be uploaded again.
+#[derive(Event)]
+pub struct DeviceRecreated;
+
+/// Sent after the runner recreated the swapchain, e.g. on resize, when the window moved to a monitor that wants
+/// another format or after [`DeviceRecreated`]. The `"Output"` format is updated by then, and
+/// [`RenderPipeline`](render_pipeline::RenderPipeline)s rebuild themselves when it changed.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct SwapchainRecreated {
+  pub extent: [u32; 2],
+  pub format: Format,
+}
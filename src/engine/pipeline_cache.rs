@@ -0,0 +1,114 @@
+use crate::engine::Resultat;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::device::{Device, Properties};
+use vulkano::pipeline::cache::{PipelineCache, PipelineCacheCreateInfo};
+
+/// Size of the `VK_PIPELINE_CACHE_HEADER_VERSION_ONE` header every pipeline cache starts with.
+const HEADER_SIZE: usize = 32;
+
+/// Whether `data` starts with a version one header written by the driver `properties` describe. Drivers are supposed
+/// to reject foreign data themselves, but not all of them do so gracefully.
+fn is_compatible(data: &[u8], properties: &Properties) -> bool {
+  header_matches(data, properties.vendor_id, properties.device_id, &properties.pipeline_cache_uuid)
+}
+
+fn header_matches(data: &[u8], vendor_id: u32, device_id: u32, pipeline_cache_uuid: &[u8; 16]) -> bool {
+  let Some(header) = data.get(..HEADER_SIZE) else {
+    return false;
+  };
+  let u32_at = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+  u32_at(0) as usize >= HEADER_SIZE
+    && u32_at(4) == 1
+    && u32_at(8) == vendor_id
+    && u32_at(12) == device_id
+    && header[16..32] == pipeline_cache_uuid[..]
+}
+
+/// The cache stored at `path`, or an empty one without a path, when there is no file yet or it was written for
+/// another device or driver version. A file that can't be read only means a slower start, so it is skipped as well.
+pub fn load(device: Arc<Device>, path: Option<&Path>) -> Resultat<Arc<PipelineCache>> {
+  let initial_data = path
+    .and_then(|path| fs::read(path).ok())
+    .filter(|data| is_compatible(data, device.physical_device().properties()))
+    .unwrap_or_default();
+  // Safety: only the header is checked, so this trusts the rest of the file to be what this driver wrote there with
+  // `save`. Anything else at `path` is up to the driver, which is meant to reject bad data but may not.
+  let cache = unsafe {
+    PipelineCache::new(
+      device,
+      PipelineCacheCreateInfo {
+        initial_data,
+        ..Default::default()
+      },
+    )?
+  };
+  Ok(cache)
+}
+
+/// Writes `cache` to `path`, through a temporary file so a crash halfway never leaves a truncated cache behind.
+pub fn save(cache: &PipelineCache, path: impl AsRef<Path>) -> Resultat<()> {
+  let path = path.as_ref();
+  let data = cache.get_data()?;
+  let temporary = path.with_extension("tmp");
+  fs::write(&temporary, data)
+    .and_then(|()| fs::rename(&temporary, path))
+    .map_err(|e| format!("{}: {e}", path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const VENDOR_ID: u32 = 0x10de;
+  const DEVICE_ID: u32 = 0x2204;
+  const UUID: [u8; 16] = [7; 16];
+
+  fn header(header_size: u32, version: u32, vendor_id: u32, device_id: u32, uuid: [u8; 16]) -> Vec<u8> {
+    [header_size, version, vendor_id, device_id]
+      .iter()
+      .flat_map(|field| field.to_le_bytes())
+      .chain(uuid)
+      .collect()
+  }
+
+  fn matches(data: &[u8]) -> bool {
+    header_matches(data, VENDOR_ID, DEVICE_ID, &UUID)
+  }
+
+  #[test]
+  fn accepts_its_own_header() {
+    let mut data = header(32, 1, VENDOR_ID, DEVICE_ID, UUID);
+    assert!(matches(&data));
+    data.extend([1, 2, 3]);
+    assert!(matches(&data));
+  }
+
+  #[test]
+  fn rejects_short_data() {
+    assert!(!matches(&[]));
+    let data = header(32, 1, VENDOR_ID, DEVICE_ID, UUID);
+    assert!(!matches(&data[..HEADER_SIZE - 1]));
+  }
+
+  #[test]
+  fn rejects_other_header_versions() {
+    assert!(!matches(&header(32, 2, VENDOR_ID, DEVICE_ID, UUID)));
+    assert!(!matches(&header(32, 0, VENDOR_ID, DEVICE_ID, UUID)));
+    assert!(!matches(&header(16, 1, VENDOR_ID, DEVICE_ID, UUID)));
+  }
+
+  #[test]
+  fn rejects_other_devices() {
+    assert!(!matches(&header(32, 1, 0x1002, DEVICE_ID, UUID)));
+    assert!(!matches(&header(32, 1, VENDOR_ID, 0x2206, UUID)));
+  }
+
+  #[test]
+  fn rejects_other_driver_builds() {
+    let mut uuid = UUID;
+    uuid[15] ^= 1;
+    assert!(!matches(&header(32, 1, VENDOR_ID, DEVICE_ID, uuid)));
+  }
+}
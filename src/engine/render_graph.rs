@@ -0,0 +1,525 @@
+use crate::engine::Resultat;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemId;
+use hashbrown::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::ImageLayout;
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+
+/// A pass of the [`RenderGraph`]: a registered system that records commands, plus the attachments it reads and
+/// writes and the passes it has to run after or before.
+pub struct RenderNode {
+  name: &'static str,
+  system: SystemId,
+  reads: Vec<&'static str>,
+  writes: Vec<&'static str>,
+  after: Vec<&'static str>,
+  before: Vec<&'static str>,
+}
+
+impl RenderNode {
+  pub fn new(name: &'static str, system: SystemId) -> Self {
+    Self {
+      name,
+      system,
+      reads: vec![],
+      writes: vec![],
+      after: vec![],
+      before: vec![],
+    }
+  }
+
+  /// The pass samples `attachment`, so it runs after every pass writing it. It can't write `attachment` itself.
+  pub fn reads(mut self, attachment: &'static str) -> Self {
+    self.reads.push(attachment);
+    self
+  }
+
+  /// The pass renders into `attachment`. The graph begins rendering to it before the pass runs.
+  pub fn writes(mut self, attachment: &'static str) -> Self {
+    self.writes.push(attachment);
+    self
+  }
+
+  pub fn after(mut self, pass: &'static str) -> Self {
+    self.after.push(pass);
+    self
+  }
+
+  pub fn before(mut self, pass: &'static str) -> Self {
+    self.before.push(pass);
+    self
+  }
+}
+
+#[derive(Clone, Debug)]
+pub enum RenderGraphError {
+  DuplicatePass(&'static str),
+  UnknownPass { pass: &'static str, dependency: &'static str },
+  UnknownAttachment { pass: &'static str, attachment: &'static str },
+  MissingInput { pass: &'static str, attachment: &'static str },
+  /// Sampling an attachment while rendering to it is a feedback loop under dynamic rendering.
+  ReadsOwnOutput { pass: &'static str, attachment: &'static str },
+  Cycle(Vec<&'static str>),
+  MissingImage(&'static str),
+}
+
+impl fmt::Display for RenderGraphError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RenderGraphError::DuplicatePass(pass) => write!(f, "render pass \"{pass}\" is added twice"),
+      RenderGraphError::UnknownPass { pass, dependency } => {
+        write!(f, "render pass \"{pass}\" depends on unknown pass \"{dependency}\"")
+      }
+      RenderGraphError::UnknownAttachment { pass, attachment } => {
+        write!(f, "render pass \"{pass}\" writes undeclared attachment \"{attachment}\"")
+      }
+      RenderGraphError::MissingInput { pass, attachment } => {
+        write!(f, "render pass \"{pass}\" reads attachment \"{attachment}\" that no pass writes")
+      }
+      RenderGraphError::ReadsOwnOutput { pass, attachment } => {
+        write!(f, "render pass \"{pass}\" reads attachment \"{attachment}\" while writing it")
+      }
+      RenderGraphError::Cycle(passes) => write!(f, "render passes form a cycle: {}", passes.join(" -> ")),
+      RenderGraphError::MissingImage(attachment) => write!(f, "no image is bound to attachment \"{attachment}\""),
+    }
+  }
+}
+
+impl Error for RenderGraphError {}
+
+/// A layout change an attachment goes through before a pass. The barriers themselves are recorded by vulkano's
+/// auto command buffer, this only makes the expected layouts visible.
+#[derive(Clone, Copy, Debug)]
+pub struct LayoutTransition {
+  pub pass: &'static str,
+  pub attachment: &'static str,
+  pub from: ImageLayout,
+  pub to: ImageLayout,
+}
+
+/// Consecutive passes sharing one `begin_rendering`/`end_rendering` scope.
+#[derive(Clone)]
+struct Scope {
+  passes: Vec<&'static str>,
+  systems: Vec<SystemId>,
+  writes: Vec<&'static str>,
+  clears: Vec<Option<[f32; 4]>>,
+  transitions: Vec<LayoutTransition>,
+}
+
+/// Replaces a flat list of systems with passes declaring what they read, write and depend on. The graph works out
+/// the execution order, the rendering scopes and the layout each attachment has to be in.
+///
+/// Attachments are declared once with [`RenderGraph::declare_attachment`] and get an image bound every frame with
+/// [`RenderGraph::set_image`]. An attachment declared with a clear color is cleared by the first pass writing it.
+#[derive(Resource, Default)]
+pub struct RenderGraph {
+  nodes: Vec<RenderNode>,
+  attachments: HashMap<&'static str, Option<[f32; 4]>>,
+  images: HashMap<&'static str, Arc<ImageView>>,
+  compiled: Option<Vec<Scope>>,
+}
+
+impl RenderGraph {
+  pub fn declare_attachment(&mut self, name: &'static str, clear: Option<[f32; 4]>) {
+    self.attachments.insert(name, clear);
+    self.compiled = None;
+  }
+
+  pub fn add_pass(&mut self, node: RenderNode) {
+    self.nodes.push(node);
+    self.compiled = None;
+  }
+
+  pub fn set_image(&mut self, attachment: &'static str, image: Arc<ImageView>) {
+    self.images.insert(attachment, image);
+  }
+
+  pub fn remove_image(&mut self, attachment: &str) {
+    self.images.remove(attachment);
+  }
+
+  pub fn image(&self, attachment: &str) -> Option<Arc<ImageView>> {
+    self.images.get(attachment).cloned()
+  }
+
+  /// Pass names in the order they run, if the graph has been compiled.
+  pub fn execution_order(&self) -> Option<Vec<&'static str>> {
+    self
+      .compiled
+      .as_ref()
+      .map(|scopes| scopes.iter().flat_map(|scope| scope.passes.iter().copied()).collect())
+  }
+
+  pub fn transitions(&self) -> Option<Vec<LayoutTransition>> {
+    self
+      .compiled
+      .as_ref()
+      .map(|scopes| scopes.iter().flat_map(|scope| scope.transitions.iter().copied()).collect())
+  }
+
+  fn sort(&self) -> Result<Vec<usize>, RenderGraphError> {
+    let mut indices = HashMap::new();
+    for (i, node) in self.nodes.iter().enumerate() {
+      if indices.insert(node.name, i).is_some() {
+        return Err(RenderGraphError::DuplicatePass(node.name));
+      }
+    }
+    let index_of = |pass: &'static str, dependency: &'static str| {
+      indices
+        .get(dependency)
+        .copied()
+        .ok_or(RenderGraphError::UnknownPass { pass, dependency })
+    };
+
+    let mut edges = vec![vec![]; self.nodes.len()];
+    for (i, node) in self.nodes.iter().enumerate() {
+      for attachment in &node.writes {
+        if !self.attachments.contains_key(attachment) {
+          return Err(RenderGraphError::UnknownAttachment {
+            pass: node.name,
+            attachment: *attachment,
+          });
+        }
+        if node.reads.contains(attachment) {
+          return Err(RenderGraphError::ReadsOwnOutput {
+            pass: node.name,
+            attachment: *attachment,
+          });
+        }
+      }
+      for dependency in &node.after {
+        edges[index_of(node.name, *dependency)?].push(i);
+      }
+      for dependency in &node.before {
+        edges[i].push(index_of(node.name, *dependency)?);
+      }
+      for attachment in &node.reads {
+        let writers = self
+          .nodes
+          .iter()
+          .enumerate()
+          .filter(|(_, writer)| writer.writes.contains(attachment))
+          .map(|(w, _)| w)
+          .collect::<Vec<_>>();
+        if writers.is_empty() {
+          return Err(RenderGraphError::MissingInput {
+            pass: node.name,
+            attachment: *attachment,
+          });
+        }
+        for w in writers {
+          edges[w].push(i);
+        }
+      }
+    }
+
+    let mut in_degree = vec![0; self.nodes.len()];
+    for to in edges.iter().flatten() {
+      in_degree[*to] += 1;
+    }
+    let mut done = vec![false; self.nodes.len()];
+    let mut order = Vec::with_capacity(self.nodes.len());
+    // Among ready passes, the one registered first wins, so independent passes keep their plugin order
+    while let Some(next) = (0..self.nodes.len()).find(|&i| !done[i] && in_degree[i] == 0) {
+      done[next] = true;
+      order.push(next);
+      for to in &edges[next] {
+        in_degree[*to] -= 1;
+      }
+    }
+
+    if order.len() < self.nodes.len() {
+      // Every pass left over still has a pending predecessor, so walking predecessors has to loop
+      let predecessor = |i: usize| (0..self.nodes.len()).find(|&p| !done[p] && edges[p].contains(&i)).unwrap();
+      let mut path = vec![(0..self.nodes.len()).find(|&i| !done[i]).unwrap()];
+      loop {
+        let p = predecessor(*path.last().unwrap());
+        if let Some(start) = path.iter().position(|&i| i == p) {
+          // The path runs against the edges and ends on `p`, so it reads forwards from `p` once reversed
+          let cycle = std::iter::once(p).chain(path[start..].iter().rev().copied());
+          return Err(RenderGraphError::Cycle(cycle.map(|i| self.nodes[i].name).collect()));
+        }
+        path.push(p);
+      }
+    }
+    Ok(order)
+  }
+
+  /// Orders the passes and groups them into rendering scopes. Called by [`RenderGraph::execute`] whenever the graph
+  /// changed, but can be called up front to surface errors early.
+  pub fn compile(&mut self) -> Result<(), RenderGraphError> {
+    if self.compiled.is_some() {
+      return Ok(());
+    }
+    let order = self.sort()?;
+
+    let mut scopes: Vec<Scope> = vec![];
+    let mut layouts = HashMap::new();
+    for i in order {
+      let node = &self.nodes[i];
+      let merge = scopes
+        .last()
+        .is_some_and(|scope| scope.writes == node.writes && !node.reads.iter().any(|a| scope.writes.contains(a)));
+      if !merge {
+        scopes.push(Scope {
+          passes: vec![],
+          systems: vec![],
+          writes: node.writes.clone(),
+          clears: node
+            .writes
+            .iter()
+            .map(|a| self.attachments[a].filter(|_| !layouts.contains_key(a)))
+            .collect(),
+          transitions: vec![],
+        });
+      }
+      let scope = scopes.last_mut().unwrap();
+      scope.passes.push(node.name);
+      scope.systems.push(node.system);
+
+      let wanted = node
+        .reads
+        .iter()
+        .map(|a| (*a, ImageLayout::ShaderReadOnlyOptimal))
+        .chain(node.writes.iter().map(|a| (*a, ImageLayout::ColorAttachmentOptimal)));
+      for (attachment, to) in wanted {
+        let from = layouts.insert(attachment, to).unwrap_or(ImageLayout::Undefined);
+        if from != to {
+          scope.transitions.push(LayoutTransition {
+            pass: node.name,
+            attachment,
+            from,
+            to,
+          });
+        }
+      }
+    }
+
+    self.compiled = Some(scopes);
+    Ok(())
+  }
+
+  /// Runs every pass in order, wrapping the ones that write attachments in `begin_rendering`/`end_rendering`.
+  pub fn execute(world: &mut World) -> Resultat<()> {
+    world.resource_mut::<RenderGraph>().compile()?;
+    let scopes = world.resource::<RenderGraph>().compiled.clone().unwrap();
+
+    for scope in scopes {
+      if !scope.writes.is_empty() {
+        let graph = world.resource::<RenderGraph>();
+        let color_attachments = scope
+          .writes
+          .iter()
+          .zip(&scope.clears)
+          .map(|(attachment, clear)| {
+            let image = graph.image(attachment).ok_or(RenderGraphError::MissingImage(*attachment))?;
+            Ok(Some(RenderingAttachmentInfo {
+              load_op: if clear.is_some() { AttachmentLoadOp::Clear } else { AttachmentLoadOp::Load },
+              store_op: AttachmentStoreOp::Store,
+              clear_value: clear.map(Into::into),
+              ..RenderingAttachmentInfo::image_view(image)
+            }))
+          })
+          .collect::<Result<Vec<_>, RenderGraphError>>()?;
+        world
+          .non_send_resource_mut::<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>()
+          .begin_rendering(RenderingInfo {
+            color_attachments,
+            ..Default::default()
+          })?;
+      }
+      for system in scope.systems {
+        world.run_system(system)?;
+      }
+      if !scope.writes.is_empty() {
+        world
+          .non_send_resource_mut::<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>()
+          .end_rendering()?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn node(world: &mut World, name: &'static str) -> RenderNode {
+    RenderNode::new(name, world.register_system(|| {}))
+  }
+
+  fn graph_of(nodes: impl FnOnce(&mut World) -> Vec<RenderNode>) -> RenderGraph {
+    let mut world = World::new();
+    let mut graph = RenderGraph::default();
+    graph.declare_attachment("Game", Some([0.0, 0.0, 0.0, 1.0]));
+    graph.declare_attachment("Output", None);
+    for node in nodes(&mut world) {
+      graph.add_pass(node);
+    }
+    graph
+  }
+
+  #[test]
+  fn orders_by_reads_writes_after_and_before() {
+    let mut graph = graph_of(|world| {
+      vec![
+        node(world, "ui").writes("Output").after("present"),
+        node(world, "present").reads("Game").writes("Output"),
+        node(world, "overlay").writes("Game").after("tiles"),
+        node(world, "tiles").writes("Game"),
+        node(world, "upload").before("tiles"),
+      ]
+    });
+    graph.compile().unwrap();
+    assert_eq!(
+      graph.execution_order().unwrap(),
+      ["upload", "tiles", "overlay", "present", "ui"]
+    );
+  }
+
+  #[test]
+  fn independent_passes_keep_their_order() {
+    let mut graph = graph_of(|world| vec![node(world, "b"), node(world, "a"), node(world, "c")]);
+    graph.compile().unwrap();
+    assert_eq!(graph.execution_order().unwrap(), ["b", "a", "c"]);
+  }
+
+  #[test]
+  fn reports_the_cycle_path() {
+    let mut graph = graph_of(|world| {
+      vec![
+        node(world, "a").after("c"),
+        node(world, "b").after("a"),
+        node(world, "c").after("b"),
+        node(world, "d").after("a"),
+      ]
+    });
+    match graph.compile() {
+      Err(RenderGraphError::Cycle(path)) => assert_eq!(path, ["a", "b", "c", "a"]),
+      other => panic!("expected a cycle, got {other:?}"),
+    }
+    assert!(graph.execution_order().is_none());
+  }
+
+  #[test]
+  fn reports_cycles_through_attachments() {
+    let mut graph = graph_of(|world| {
+      vec![
+        node(world, "a").reads("Game").writes("Output").before("b"),
+        node(world, "b").writes("Game"),
+      ]
+    });
+    match graph.compile() {
+      Err(RenderGraphError::Cycle(path)) => assert_eq!(path, ["a", "b", "a"]),
+      other => panic!("expected a cycle, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn reports_missing_inputs() {
+    let mut graph = graph_of(|world| vec![node(world, "present").reads("Game").writes("Output")]);
+    assert!(matches!(
+      graph.compile(),
+      Err(RenderGraphError::MissingInput {
+        pass: "present",
+        attachment: "Game"
+      })
+    ));
+  }
+
+  #[test]
+  fn reports_unknown_passes_and_attachments() {
+    let mut graph = graph_of(|world| vec![node(world, "a").after("b")]);
+    assert!(matches!(
+      graph.compile(),
+      Err(RenderGraphError::UnknownPass { pass: "a", dependency: "b" })
+    ));
+    let mut graph = graph_of(|world| vec![node(world, "a").writes("Shadow")]);
+    assert!(matches!(
+      graph.compile(),
+      Err(RenderGraphError::UnknownAttachment {
+        pass: "a",
+        attachment: "Shadow"
+      })
+    ));
+    let mut graph = graph_of(|world| vec![node(world, "a"), node(world, "a")]);
+    assert!(matches!(graph.compile(), Err(RenderGraphError::DuplicatePass("a"))));
+  }
+
+  #[test]
+  fn groups_passes_into_rendering_scopes() {
+    let mut graph = graph_of(|world| {
+      vec![
+        node(world, "upload"),
+        node(world, "tiles").writes("Game"),
+        node(world, "sprites").writes("Game").after("tiles"),
+        node(world, "present").reads("Game").writes("Output"),
+        node(world, "ui").writes("Output").after("present"),
+      ]
+    });
+    graph.compile().unwrap();
+    let scopes = graph.compiled.as_ref().unwrap();
+    let passes = scopes.iter().map(|scope| scope.passes.clone()).collect::<Vec<_>>();
+    assert_eq!(passes, [vec!["upload"], vec!["tiles", "sprites"], vec!["present", "ui"]]);
+    let writes = scopes.iter().map(|scope| scope.writes.clone()).collect::<Vec<_>>();
+    assert_eq!(writes, [vec![], vec!["Game"], vec!["Output"]]);
+    let clears = scopes.iter().map(|scope| scope.clears.clone()).collect::<Vec<_>>();
+    assert_eq!(clears, [vec![], vec![Some([0.0, 0.0, 0.0, 1.0])], vec![None]]);
+
+    let transitions = graph
+      .transitions()
+      .unwrap()
+      .into_iter()
+      .map(|t| (t.pass, t.attachment, t.from, t.to))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      transitions,
+      [
+        ("tiles", "Game", ImageLayout::Undefined, ImageLayout::ColorAttachmentOptimal),
+        ("present", "Game", ImageLayout::ColorAttachmentOptimal, ImageLayout::ShaderReadOnlyOptimal),
+        ("present", "Output", ImageLayout::Undefined, ImageLayout::ColorAttachmentOptimal),
+      ]
+    );
+  }
+
+  #[test]
+  fn rejects_passes_reading_what_they_write() {
+    let mut graph = graph_of(|world| {
+      vec![
+        node(world, "tiles").writes("Game"),
+        node(world, "blur").reads("Game").writes("Game"),
+      ]
+    });
+    assert!(matches!(
+      graph.compile(),
+      Err(RenderGraphError::ReadsOwnOutput {
+        pass: "blur",
+        attachment: "Game"
+      })
+    ));
+  }
+
+  #[test]
+  fn only_the_first_scope_writing_an_attachment_clears_it() {
+    let mut graph = graph_of(|world| {
+      vec![
+        node(world, "tiles").writes("Game"),
+        node(world, "present").reads("Game").writes("Output"),
+        node(world, "sprites").writes("Game").after("present"),
+      ]
+    });
+    graph.compile().unwrap();
+    let scopes = graph.compiled.as_ref().unwrap();
+    let passes = scopes.iter().map(|scope| scope.passes.clone()).collect::<Vec<_>>();
+    assert_eq!(passes, [vec!["tiles"], vec!["present"], vec!["sprites"]]);
+    let clears = scopes.iter().map(|scope| scope.clears.clone()).collect::<Vec<_>>();
+    assert_eq!(clears, [vec![Some([0.0, 0.0, 0.0, 1.0])], vec![None], vec![None]]);
+  }
+}
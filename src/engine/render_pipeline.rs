@@ -0,0 +1,198 @@
+use crate::engine::error::EngineError;
+use crate::engine::internals::DeviceInitializers;
+use crate::engine::render_graph::{RenderGraph, RenderNode};
+use crate::engine::shader_reload::ShaderReloader;
+use crate::engine::{
+  debug_name, handle_result, ASingleton, AssociatedResource, NamedSingleton, Resultat, SwapchainRecreated,
+};
+use bevy_app::{App, Last, Plugin};
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemId;
+use smallvec::smallvec;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::{PipelineRenderingCreateInfo, PipelineSubpassType};
+use vulkano::pipeline::graphics::vertex_input::{VertexBufferDescription, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{DynamicState, GraphicsPipeline, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::shader::EntryPoint;
+
+/// A graphics pipeline drawing into one attachment of the `"Output"` format, declared rather than built by hand.
+/// [`RenderPipelinePlugin`] builds it on every device and stores it as `AssociatedResource<Self,
+/// Arc<GraphicsPipeline>>`, rebuilds it when the output format changes or its shaders are reloaded, and adds its
+/// pass to the [`RenderGraph`]. Descriptor set layouts and push constant ranges are reflected from the shaders.
+pub trait RenderPipeline: Send + Sync + Sized + 'static {
+  /// Name of the pass in the render graph and of the pipeline in the [`ShaderReloader`].
+  const NAME: &'static str;
+  /// GLSL the compiled-in shaders are built from, watched for changes with hot reloading on.
+  const VERTEX_SHADER: &'static str;
+  const FRAGMENT_SHADER: &'static str;
+  const DYNAMIC_STATE: &'static [DynamicState] = &[DynamicState::Viewport];
+
+  /// The compiled-in shaders, loaded from the pipeline's `vulkano_shaders::shader!` modules.
+  fn shaders(device: Arc<Device>) -> Resultat<(EntryPoint, EntryPoint)>;
+
+  /// The vertex buffer layout, e.g. `MyVertex::per_vertex()`.
+  fn vertex_buffer() -> VertexBufferDescription;
+
+  fn input_assembly() -> InputAssemblyState {
+    InputAssemblyState::default()
+  }
+
+  /// How the pipeline blends into the attachment, alpha blending unless overridden.
+  fn blend() -> Option<AttachmentBlend> {
+    Some(AttachmentBlend::alpha())
+  }
+
+  /// Override to change what is reflected from `stages`, e.g. to make a sampler immutable.
+  fn layout(stages: &[PipelineShaderStageCreateInfo]) -> PipelineDescriptorSetLayoutCreateInfo {
+    PipelineDescriptorSetLayoutCreateInfo::from_stages(stages)
+  }
+
+  /// Registers the system recording the pass. The default only binds the pipeline.
+  fn bind_system(world: &mut World) -> SystemId {
+    world.register_system(bind::<Self>.pipe(handle_result))
+  }
+
+  /// Places the pass, a node named [`NAME`](Self::NAME) running the bind system, in the render graph.
+  fn node(node: RenderNode) -> RenderNode;
+}
+
+fn bind<P: RenderPipeline>(
+  mut builder: NonSendMut<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>,
+  pipeline: Res<AssociatedResource<P, Arc<GraphicsPipeline>>>,
+) -> Resultat<()> {
+  builder.bind_pipeline_graphics(pipeline.clone())?;
+  Ok(())
+}
+
+fn pipeline<P: RenderPipeline>(
+  device: Arc<Device>,
+  cache: Arc<PipelineCache>,
+  output_format: Format,
+  vs: EntryPoint,
+  fs: EntryPoint,
+) -> Resultat<Arc<GraphicsPipeline>> {
+  let vertex_input_state = P::vertex_buffer().definition(&vs.info().input_interface)?;
+  let stages = smallvec![
+    PipelineShaderStageCreateInfo::new(vs),
+    PipelineShaderStageCreateInfo::new(fs),
+  ];
+  let layout = PipelineLayout::new(
+    device.clone(),
+    P::layout(&stages).into_pipeline_layout_create_info(device.clone())?,
+  )?;
+  let subpass = PipelineRenderingCreateInfo {
+    color_attachment_formats: vec![Some(output_format)],
+    ..Default::default()
+  };
+  let pipeline = GraphicsPipeline::new(
+    device.clone(),
+    Some(cache),
+    GraphicsPipelineCreateInfo {
+      stages,
+      vertex_input_state: Some(vertex_input_state),
+      input_assembly_state: Some(P::input_assembly()),
+      viewport_state: Some(ViewportState::default()),
+      rasterization_state: Some(RasterizationState::default()),
+      multisample_state: Some(MultisampleState::default()),
+      color_blend_state: Some(ColorBlendState::with_attachment_states(
+        subpass.color_attachment_formats.len() as u32,
+        ColorBlendAttachmentState {
+          blend: P::blend(),
+          ..Default::default()
+        },
+      )),
+      dynamic_state: P::DYNAMIC_STATE.iter().copied().collect(),
+      subpass: Some(subpass.into()),
+      ..GraphicsPipelineCreateInfo::layout(layout)
+    },
+  )?;
+  debug_name::<P>(&*pipeline, "pipeline");
+  Ok(pipeline)
+}
+
+/// Builds `P` for the current device and output format. Also the [`ShaderReloader`] hook.
+fn rebuild<P: RenderPipeline>(world: &World, vs: EntryPoint, fs: EntryPoint) -> Resultat<Arc<GraphicsPipeline>> {
+  let device = world.resource::<ASingleton<Device>>().clon();
+  let pipeline_cache = world.resource::<ASingleton<PipelineCache>>().clon();
+  let output_format = **world.resource::<NamedSingleton<"Output", Format>>();
+  pipeline::<P>(device, pipeline_cache, output_format, vs, fs).map_err(|e| EngineError::PipelineCreation(e.into()))
+}
+
+fn init<P: RenderPipeline>(world: &mut World) -> Resultat<()> {
+  let device = world.resource::<ASingleton<Device>>().clon();
+  let (vs, fs) = P::shaders(device).map_err(|e| EngineError::ShaderLoad(e.into()))?;
+  let pipeline = rebuild::<P>(world, vs, fs)?;
+  world.insert_resource(AssociatedResource::<P, _>::new(pipeline));
+  Ok(())
+}
+
+/// The format of the attachment `pipeline` was built for.
+fn attachment_format(pipeline: &GraphicsPipeline) -> Option<Format> {
+  match pipeline.subpass() {
+    PipelineSubpassType::BeginRendering(rendering) => rendering.color_attachment_formats.first().copied().flatten(),
+    // The engine only renders dynamically
+    _ => None,
+  }
+}
+
+/// Rebuilds `P` from the compiled-in shaders when the swapchain came back with another format, the reloader then
+/// compiles any reloaded ones again.
+fn follow_output_format<P: RenderPipeline>(
+  mut recreated: EventReader<SwapchainRecreated>,
+  device: Res<ASingleton<Device>>,
+  pipeline_cache: Res<ASingleton<PipelineCache>>,
+  mut pipeline: ResMut<AssociatedResource<P, Arc<GraphicsPipeline>>>,
+  mut reloader: ResMut<ShaderReloader>,
+) -> Resultat<()> {
+  let Some(format) = recreated.read().last().map(|recreated| recreated.format) else {
+    return Ok(());
+  };
+  if attachment_format(&pipeline) == Some(format) {
+    return Ok(());
+  }
+  let (vs, fs) = P::shaders(device.clon()).map_err(|e| EngineError::ShaderLoad(e.into()))?;
+  **pipeline = pipeline::<P>(device.clon(), pipeline_cache.clon(), format, vs, fs)
+    .map_err(|e| EngineError::PipelineCreation(e.into()))?;
+  reloader.restore(P::NAME);
+  Ok(())
+}
+
+/// Builds and maintains the pipeline of a [`RenderPipeline`]. Pipelines needing more device resources, such as
+/// descriptor sets for the pipeline's layout, register their own [`DeviceInitializers`] after adding this plugin so
+/// the pipeline is there when they run.
+pub struct RenderPipelinePlugin<P>(PhantomData<fn() -> P>);
+
+impl<P> Default for RenderPipelinePlugin<P> {
+  fn default() -> Self {
+    Self(PhantomData)
+  }
+}
+
+impl<P: RenderPipeline> Plugin for RenderPipelinePlugin<P> {
+  fn build(&self, app: &mut App) {
+    app.world.resource_mut::<DeviceInitializers>().register(init::<P>);
+    app
+      .world
+      .resource_mut::<ShaderReloader>()
+      .register::<P>(P::NAME, P::VERTEX_SHADER, P::FRAGMENT_SHADER, rebuild::<P>);
+    app.add_systems(Last, follow_output_format::<P>.pipe(handle_result));
+
+    let system_id = P::bind_system(&mut app.world);
+    app
+      .world
+      .resource_mut::<RenderGraph>()
+      .add_pass(P::node(RenderNode::new(P::NAME, system_id)));
+  }
+}
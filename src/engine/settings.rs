@@ -0,0 +1,263 @@
+use crate::engine::device::DeviceSelector;
+use crate::engine::engine::RenderMode;
+use crate::engine::Resultat;
+use bevy_ecs::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use vulkano::format::Format;
+use vulkano::swapchain::{ColorSpace, PresentMode};
+
+/// Everything [`RuminativeEnginePlugin`](crate::engine::engine::RuminativeEnginePlugin) needs to know before it
+/// creates the window and swapchain. Missing fields in a RON file fall back to their defaults, e.g.
+///
+/// ```ron
+/// (
+///   window: (title: "Editor", size: (1600, 900), fullscreen: Borderless),
+///   swapchain: (present_modes: [Mailbox, Fifo], image_count: Some(3), format: Some(B8G8R8A8_SRGB)),
+/// )
+/// ```
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EngineSettings {
+  pub mode: RenderMode,
+  /// How many frames the CPU may record ahead of the GPU.
+  pub frames_in_flight: usize,
+  /// Forces a GPU instead of the best ranked one. The `RUMINATIVE_DEVICE` environment variable overrides it.
+  pub device: Option<DeviceSelector>,
+  /// Enables the Khronos validation layer where installed and logs its messages to the
+  /// [`EngineLog`](crate::engine::log::EngineLog). Slows everything down, meant for development and tests.
+  pub debug: bool,
+  /// Where compiled pipelines are kept between runs, best somewhere in the user's cache directory. `None`, the
+  /// default, compiles every pipeline from scratch on each start.
+  pub pipeline_cache: Option<PathBuf>,
+  /// Recompiles shaders in `assets/shaders` when they change and rebuilds their pipelines, for development.
+  pub hot_reload_shaders: bool,
+  pub window: WindowSettings,
+  pub swapchain: SwapchainSettings,
+}
+
+impl Default for EngineSettings {
+  fn default() -> Self {
+    Self {
+      mode: RenderMode::default(),
+      frames_in_flight: 2,
+      device: None,
+      debug: false,
+      pipeline_cache: None,
+      hot_reload_shaders: false,
+      window: WindowSettings::default(),
+      swapchain: SwapchainSettings::default(),
+    }
+  }
+}
+
+impl EngineSettings {
+  pub fn from_ron_str(ron: &str) -> Resultat<Self> {
+    let settings: Self = ron::from_str(ron)?;
+    if settings.frames_in_flight == 0 {
+      return Err("frames_in_flight has to be at least 1".into());
+    }
+    Ok(settings)
+  }
+
+  pub fn from_ron_file(path: impl AsRef<Path>) -> Resultat<Self> {
+    let path = path.as_ref();
+    let ron = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    Self::from_ron_str(&ron).map_err(|e| format!("{}: {e}", path.display()).into())
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FullscreenMode {
+  #[default]
+  Windowed,
+  /// A borderless window covering the current monitor.
+  Borderless,
+  /// Takes over the primary monitor with its largest video mode, or falls back to borderless.
+  Exclusive,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+  pub title: String,
+  /// Initial inner size in logical pixels.
+  pub size: [u32; 2],
+  pub resizable: bool,
+  pub fullscreen: FullscreenMode,
+}
+
+impl Default for WindowSettings {
+  fn default() -> Self {
+    Self {
+      title: "Ruminative".into(),
+      size: [1280, 720],
+      resizable: true,
+      fullscreen: FullscreenMode::Windowed,
+    }
+  }
+}
+
+/// Mirrors the [`PresentMode`]s every platform knows, so they can be written in settings files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PresentModePreference {
+  Immediate,
+  Mailbox,
+  Fifo,
+  FifoRelaxed,
+}
+
+impl From<PresentModePreference> for PresentMode {
+  fn from(mode: PresentModePreference) -> Self {
+    match mode {
+      PresentModePreference::Immediate => PresentMode::Immediate,
+      PresentModePreference::Mailbox => PresentMode::Mailbox,
+      PresentModePreference::Fifo => PresentMode::Fifo,
+      PresentModePreference::FifoRelaxed => PresentMode::FifoRelaxed,
+    }
+  }
+}
+
+/// Mirrors the [`Format`]s surfaces commonly offer, so they can be written in settings files.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FormatPreference {
+  B8G8R8A8_UNORM,
+  B8G8R8A8_SRGB,
+  R8G8B8A8_UNORM,
+  R8G8B8A8_SRGB,
+  A8B8G8R8_UNORM_PACK32,
+  A8B8G8R8_SRGB_PACK32,
+  A2B10G10R10_UNORM_PACK32,
+  A2R10G10B10_UNORM_PACK32,
+  R16G16B16A16_SFLOAT,
+}
+
+impl From<FormatPreference> for Format {
+  fn from(format: FormatPreference) -> Self {
+    match format {
+      FormatPreference::B8G8R8A8_UNORM => Format::B8G8R8A8_UNORM,
+      FormatPreference::B8G8R8A8_SRGB => Format::B8G8R8A8_SRGB,
+      FormatPreference::R8G8B8A8_UNORM => Format::R8G8B8A8_UNORM,
+      FormatPreference::R8G8B8A8_SRGB => Format::R8G8B8A8_SRGB,
+      FormatPreference::A8B8G8R8_UNORM_PACK32 => Format::A8B8G8R8_UNORM_PACK32,
+      FormatPreference::A8B8G8R8_SRGB_PACK32 => Format::A8B8G8R8_SRGB_PACK32,
+      FormatPreference::A2B10G10R10_UNORM_PACK32 => Format::A2B10G10R10_UNORM_PACK32,
+      FormatPreference::A2R10G10B10_UNORM_PACK32 => Format::A2R10G10B10_UNORM_PACK32,
+      FormatPreference::R16G16B16A16_SFLOAT => Format::R16G16B16A16_SFLOAT,
+    }
+  }
+}
+
+/// Mirrors the [`ColorSpace`]s surfaces commonly offer, so they can be written in settings files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorSpacePreference {
+  SrgbNonLinear,
+  ExtendedSrgbLinear,
+  ExtendedSrgbNonLinear,
+  DisplayP3NonLinear,
+  Bt2020Linear,
+  Hdr10St2084,
+  PassThrough,
+}
+
+impl From<ColorSpacePreference> for ColorSpace {
+  fn from(color_space: ColorSpacePreference) -> Self {
+    match color_space {
+      ColorSpacePreference::SrgbNonLinear => ColorSpace::SrgbNonLinear,
+      ColorSpacePreference::ExtendedSrgbLinear => ColorSpace::ExtendedSrgbLinear,
+      ColorSpacePreference::ExtendedSrgbNonLinear => ColorSpace::ExtendedSrgbNonLinear,
+      ColorSpacePreference::DisplayP3NonLinear => ColorSpace::DisplayP3NonLinear,
+      ColorSpacePreference::Bt2020Linear => ColorSpace::Bt2020Linear,
+      ColorSpacePreference::Hdr10St2084 => ColorSpace::Hdr10St2084,
+      ColorSpacePreference::PassThrough => ColorSpace::PassThrough,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SwapchainSettings {
+  /// Tried in order, the first one the surface supports wins. Falls back to `Fifo`, which is always supported.
+  pub present_modes: Vec<PresentModePreference>,
+  /// Clamped to what the surface allows. `None` uses the surface's minimum.
+  pub image_count: Option<u32>,
+  /// Used if the surface supports it, together with `color_space` if that is set too. `None` or an unsupported
+  /// format picks an 8-bit per channel format if there is one.
+  pub format: Option<FormatPreference>,
+  pub color_space: Option<ColorSpacePreference>,
+}
+
+impl Default for SwapchainSettings {
+  fn default() -> Self {
+    Self {
+      present_modes: vec![PresentModePreference::Fifo],
+      image_count: None,
+      format: None,
+      color_space: None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_ron() {
+    let settings = EngineSettings::from_ron_str(
+      r#"(
+        mode: Headless(extent: (64, 32), frames: Some(3)),
+        frames_in_flight: 3,
+        device: Some(Index(1)),
+        window: (title: "Editor", fullscreen: Borderless),
+        swapchain: (present_modes: [Mailbox, Fifo], format: Some(B8G8R8A8_SRGB), color_space: Some(SrgbNonLinear)),
+      )"#,
+    )
+    .unwrap();
+    assert!(matches!(
+      settings.mode,
+      RenderMode::Headless {
+        extent: [64, 32],
+        frames: Some(3)
+      }
+    ));
+    assert_eq!(settings.frames_in_flight, 3);
+    assert_eq!(settings.device, Some(DeviceSelector::Index(1)));
+    assert_eq!(settings.window.title, "Editor");
+    assert_eq!(settings.window.size, WindowSettings::default().size);
+    assert_eq!(settings.window.fullscreen, FullscreenMode::Borderless);
+    assert_eq!(
+      settings.swapchain.present_modes,
+      [PresentModePreference::Mailbox, PresentModePreference::Fifo]
+    );
+    assert_eq!(settings.swapchain.format.map(Format::from), Some(Format::B8G8R8A8_SRGB));
+    assert_eq!(settings.swapchain.color_space.map(ColorSpace::from), Some(ColorSpace::SrgbNonLinear));
+  }
+
+  #[test]
+  fn round_trips_through_ron() {
+    let mut settings = EngineSettings::default();
+    settings.frames_in_flight = 3;
+    settings.hot_reload_shaders = true;
+    settings.pipeline_cache = Some("cache/pipeline.cache".into());
+    settings.swapchain.format = Some(FormatPreference::A2B10G10R10_UNORM_PACK32);
+    settings.swapchain.color_space = Some(ColorSpacePreference::Hdr10St2084);
+    let ron = ron::to_string(&settings).unwrap();
+    let parsed = EngineSettings::from_ron_str(&ron).unwrap();
+    assert_eq!(format!("{parsed:?}"), format!("{settings:?}"));
+  }
+
+  #[test]
+  fn rejects_unknown_formats() {
+    assert!(EngineSettings::from_ron_str("(swapchain: (format: Some(B8G8R8A8_SRBG)))").is_err());
+    assert!(EngineSettings::from_ron_str("(swapchain: (color_space: Some(Srgb)))").is_err());
+  }
+
+  #[test]
+  fn rejects_no_frames_in_flight() {
+    assert!(EngineSettings::from_ron_str("(frames_in_flight: 0)").is_err());
+    assert_eq!(EngineSettings::from_ron_str("()").unwrap().frames_in_flight, 2);
+  }
+}
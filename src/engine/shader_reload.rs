@@ -0,0 +1,290 @@
+use crate::engine::error::EngineError;
+use crate::engine::internals::DeviceInitializers;
+use crate::engine::log::{EngineLog, LogLevel, LogSource};
+use crate::engine::{ASingleton, AssociatedResource, Resultat};
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_ecs::prelude::*;
+use shaderc::{
+  CompileOptions, Compiler, EnvVersion, IncludeCallbackResult, IncludeType, ResolvedInclude, ShaderKind, TargetEnv,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use vulkano::device::Device;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::shader::{EntryPoint, ShaderModule, ShaderModuleCreateInfo};
+
+/// How often the shader files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Shared GLSL that shaders pull in with `#include <...>`. The compiled-in shaders list the same directory in the
+/// `include` of their `vulkano_shaders::shader!`.
+pub const SHADER_LIBRARY: &str = "assets/shaders/lib";
+
+/// `path` relative to the crate root, which is where `vulkano_shaders::shader!` looks for shaders at build time, so
+/// reloading works from any working directory.
+pub fn asset_path(path: impl AsRef<Path>) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+/// Resolves `#include` like `vulkano_shaders` does at build time: `"..."` next to the including file and `<...>` in
+/// [`SHADER_LIBRARY`]. Included files keep their path as name, so compile errors point at the right file and line.
+pub fn resolve_include(
+  requested: &str,
+  include_type: IncludeType,
+  requesting: &str,
+  _depth: usize,
+) -> IncludeCallbackResult {
+  let path = match include_type {
+    IncludeType::Relative => Path::new(requesting).parent().unwrap_or(Path::new("")).join(requested),
+    IncludeType::Standard => asset_path(SHADER_LIBRARY).join(requested),
+  };
+  let content = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+  Ok(ResolvedInclude {
+    resolved_name: path.display().to_string(),
+    content,
+  })
+}
+
+/// Builds a pipeline from freshly compiled vertex and fragment shaders.
+pub type RebuildPipeline = fn(&World, EntryPoint, EntryPoint) -> Resultat<Arc<GraphicsPipeline>>;
+
+struct ReloadablePipeline {
+  name: &'static str,
+  vertex: &'static str,
+  fragment: &'static str,
+  /// Latest modification time of either file when it was last looked at.
+  modified: Option<SystemTime>,
+  /// Compile on the next poll even if nothing changed.
+  stale: bool,
+  reloaded: bool,
+  error: Option<String>,
+  replace: Box<dyn Fn(&mut World, EntryPoint, EntryPoint) -> Resultat<()> + Send + Sync>,
+}
+
+impl ReloadablePipeline {
+  /// Counts the whole [`SHADER_LIBRARY`] in, since the shaders may include any of it.
+  fn latest_modification(&self) -> Option<SystemTime> {
+    let library = fs::read_dir(asset_path(SHADER_LIBRARY))
+      .into_iter()
+      .flatten()
+      .filter_map(|entry| Some(entry.ok()?.path()));
+    [asset_path(self.vertex), asset_path(self.fragment)]
+      .into_iter()
+      .chain(library)
+      .filter_map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+      .max()
+  }
+}
+
+/// Pipelines whose GLSL in `assets/shaders` is recompiled at runtime when it changes, with
+/// [`EngineSettings::hot_reload_shaders`](crate::engine::settings::EngineSettings::hot_reload_shaders). The new
+/// shaders must keep the descriptor and push constant layout the compiled-in ones have, since descriptor sets and
+/// push constant structs are generated from those.
+#[derive(Resource, Default)]
+pub struct ShaderReloader {
+  pipelines: Vec<ReloadablePipeline>,
+  last_poll: Option<Instant>,
+}
+
+impl ShaderReloader {
+  /// Watches `vertex` and `fragment`, replacing the `AssociatedResource<P, Arc<GraphicsPipeline>>` with what
+  /// `rebuild` makes of them after every change. When they don't compile the old pipeline stays.
+  pub fn register<P: Send + Sync + 'static>(
+    &mut self,
+    name: &'static str,
+    vertex: &'static str,
+    fragment: &'static str,
+    rebuild: RebuildPipeline,
+  ) {
+    let mut pipeline = ReloadablePipeline {
+      name,
+      vertex,
+      fragment,
+      modified: None,
+      stale: false,
+      reloaded: false,
+      error: None,
+      replace: Box::new(move |world, vs, fs| {
+        let pipeline = rebuild(world, vs, fs)?;
+        world.insert_resource(AssociatedResource::<P, _>::new(pipeline));
+        Ok(())
+      }),
+    };
+    // The compiled-in shaders are current until a file changes
+    pipeline.modified = pipeline.latest_modification();
+    self.pipelines.push(pipeline);
+  }
+
+  /// Compile errors of the pipelines whose last reload failed, by pipeline name.
+  pub fn errors(&self) -> impl Iterator<Item = (&str, &str)> {
+    self
+      .pipelines
+      .iter()
+      .filter_map(|pipeline| Some((pipeline.name, pipeline.error.as_deref()?)))
+  }
+
+  /// Compiles the GLSL at `path`, see [`asset_path`], to SPIR-V with includes resolved by [`resolve_include`].
+  fn spirv(path: &str, kind: ShaderKind) -> Resultat<Vec<u32>> {
+    let path = asset_path(path).display().to_string();
+    let source = fs::read_to_string(&path).map_err(|e| format!("{path}: {e}"))?;
+    let compiler = Compiler::new().ok_or("failed to start the shader compiler")?;
+    let mut options = CompileOptions::new().ok_or("failed to create shader compile options")?;
+    options.set_target_env(TargetEnv::Vulkan, EnvVersion::Vulkan1_0 as u32);
+    options.set_include_callback(resolve_include);
+    let artifact = compiler
+      .compile_into_spirv(&source, kind, &path, "main", Some(&options))
+      .map_err(|e| EngineError::ShaderLoad(e.into()))?;
+    Ok(artifact.as_binary().to_vec())
+  }
+
+  fn compile(device: Arc<Device>, path: &str, kind: ShaderKind) -> Resultat<EntryPoint> {
+    let spirv = Self::spirv(path, kind)?;
+    // Safety: shaderc only produces valid SPIR-V
+    let module = unsafe { ShaderModule::new(device, ShaderModuleCreateInfo::new(&spirv)) }
+      .map_err(|e| EngineError::ShaderLoad(e.into()))?;
+    Ok(module.entry_point("main").ok_or(format!("{path}: no main entry point"))?)
+  }
+
+  /// Recompiles every pipeline whose shader files changed since the last poll.
+  fn reload(world: &mut World) {
+    world.resource_scope(|world, mut reloader: Mut<ShaderReloader>| {
+      let now = Instant::now();
+      if reloader.last_poll.is_some_and(|last_poll| now - last_poll < POLL_INTERVAL) {
+        return;
+      }
+      reloader.last_poll = Some(now);
+
+      let device = world.resource::<ASingleton<Device>>().clon();
+      let log = world.resource::<EngineLog>().clone();
+      for pipeline in reloader.pipelines.iter_mut() {
+        let modified = pipeline.latest_modification();
+        if !pipeline.stale && modified == pipeline.modified {
+          continue;
+        }
+        pipeline.modified = modified;
+        pipeline.stale = false;
+
+        let reloaded = Self::compile(device.clone(), pipeline.vertex, ShaderKind::Vertex)
+          .and_then(|vs| Ok((vs, Self::compile(device.clone(), pipeline.fragment, ShaderKind::Fragment)?)))
+          .and_then(|(vs, fs)| (pipeline.replace)(world, vs, fs));
+        match reloaded {
+          Ok(()) => {
+            log.push(LogLevel::Info, LogSource::Engine, format!("reloaded {} shaders", pipeline.name));
+            pipeline.reloaded = true;
+            pipeline.error = None;
+          }
+          Err(e) => {
+            log.push(LogLevel::Error, LogSource::Engine, format!("{}: {e}", pipeline.name));
+            pipeline.error = Some(e.to_string());
+          }
+        }
+      }
+    });
+  }
+
+  /// Call after building `name`'s pipeline from the compiled-in shaders again, so reloaded ones replace it on the
+  /// next poll.
+  pub fn restore(&mut self, name: &str) {
+    for pipeline in self.pipelines.iter_mut().filter(|pipeline| pipeline.name == name) {
+      pipeline.stale |= pipeline.reloaded;
+    }
+  }
+
+  /// A new device starts out with the compiled-in shaders, so anything reloaded before is compiled again.
+  fn reset(world: &mut World) -> Resultat<()> {
+    for pipeline in world.resource_mut::<ShaderReloader>().pipelines.iter_mut() {
+      pipeline.stale |= pipeline.reloaded;
+    }
+    Ok(())
+  }
+}
+
+/// Polls the files registered with [`ShaderReloader`] at the start of every frame. Added by the engine when
+/// [`EngineSettings::hot_reload_shaders`](crate::engine::settings::EngineSettings::hot_reload_shaders) is set.
+pub struct ShaderReloadPlugin;
+
+impl Plugin for ShaderReloadPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<ShaderReloader>();
+    app.world.resource_mut::<DeviceInitializers>().register(ShaderReloader::reset);
+    app.add_systems(PreUpdate, ShaderReloader::reload);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A directory of its own under the system temp directory, removed again when dropped.
+  struct ShaderDir(PathBuf);
+
+  impl ShaderDir {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!("ruminative-{name}-{}", std::process::id()));
+      fs::create_dir_all(&dir).unwrap();
+      Self(dir)
+    }
+
+    fn write(&self, file: &str, glsl: &str) -> String {
+      let path = self.0.join(file);
+      fs::write(&path, glsl).unwrap();
+      path.display().to_string()
+    }
+  }
+
+  impl Drop for ShaderDir {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  const FRAGMENT: &str = "#version 450
+#include <srgb.glsl>
+#include \"helper.glsl\"
+
+layout(location = 0) out vec4 color;
+
+void main() {
+  color = linear_to_srgb(vec4(helper()));
+}
+";
+
+  #[test]
+  fn compiles_library_and_relative_includes() {
+    let dir = ShaderDir::new("includes");
+    dir.write("helper.glsl", "float helper() {\n  return 0.5;\n}\n");
+    let fragment = dir.write("fragment.glsl", FRAGMENT);
+    assert!(!ShaderReloader::spirv(&fragment, ShaderKind::Fragment).unwrap().is_empty());
+  }
+
+  #[test]
+  fn errors_name_the_included_file_and_line() {
+    let dir = ShaderDir::new("broken-include");
+    let helper = dir.write("helper.glsl", "// Broken on purpose\nfloat helper() {\n  return 0.5 +;\n}\n");
+    let fragment = dir.write("fragment.glsl", FRAGMENT);
+    let error = ShaderReloader::spirv(&fragment, ShaderKind::Fragment).unwrap_err().to_string();
+    assert!(error.contains(&format!("{helper}:3:")), "{error}");
+  }
+
+  #[test]
+  fn compiles_the_engine_shaders() {
+    for (vertex, fragment) in [
+      ("assets/shaders/vertex.glsl", "assets/shaders/fragment.glsl"),
+      ("assets/shaders/imgui_vertex.glsl", "assets/shaders/imgui_fragment.glsl"),
+      ("assets/shaders/rumigui_vertex.glsl", "assets/shaders/rumigui_fragment.glsl"),
+    ] {
+      ShaderReloader::spirv(vertex, ShaderKind::Vertex).unwrap();
+      ShaderReloader::spirv(fragment, ShaderKind::Fragment).unwrap();
+    }
+  }
+
+  #[test]
+  fn missing_includes_are_errors() {
+    let dir = ShaderDir::new("missing-include");
+    let fragment = dir.write("fragment.glsl", FRAGMENT);
+    let error = ShaderReloader::spirv(&fragment, ShaderKind::Fragment).unwrap_err().to_string();
+    assert!(error.contains("helper.glsl"), "{error}");
+  }
+}
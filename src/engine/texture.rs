@@ -0,0 +1,266 @@
+use crate::engine::{debug_name, ASingleton, Resultat};
+use bevy_ecs::prelude::*;
+use png::{ColorType, Transformations};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{
+  AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferToImageInfo, PrimaryCommandBufferAbstract,
+};
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::{DeviceOwned, Queue};
+use vulkano::format::Format;
+use vulkano::image::sampler::{Sampler, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::sync::GpuFuture;
+use vulkano::DeviceSize;
+
+/// Decodes a PNG into tightly packed 8-bit RGBA pixels. Palette, grayscale and low bit depth images are expanded,
+/// 16-bit channels are truncated to their high byte.
+pub fn decode_png(reader: impl Read) -> Resultat<([u32; 2], Vec<u8>)> {
+  let mut decoder = png::Decoder::new(reader);
+  decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+  let mut reader = decoder.read_info()?;
+  let mut data = vec![0; reader.output_buffer_size()];
+  let info = reader.next_frame(&mut data)?;
+  data.truncate(info.buffer_size());
+
+  let rgba = match info.color_type {
+    ColorType::Rgba => data,
+    ColorType::Rgb => data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+    ColorType::GrayscaleAlpha => data.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+    ColorType::Grayscale => data.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+    ColorType::Indexed => return Err("palette was not expanded".into()),
+  };
+  Ok(([info.width, info.height], rgba))
+}
+
+/// Bytes of tightly packed RGBA pixels in an image of `extent`, `None` if that doesn't fit in memory.
+fn rgba8_size(extent: [u32; 2]) -> Option<usize> {
+  (extent[0] as usize).checked_mul(extent[1] as usize)?.checked_mul(4)
+}
+
+/// A sampled image on the GPU that any pipeline can bind, usually through [`Texture::descriptor`].
+#[derive(Clone)]
+pub struct Texture {
+  pub view: Arc<ImageView>,
+  pub sampler: Arc<Sampler>,
+}
+
+impl Texture {
+  pub fn extent(&self) -> [u32; 2] {
+    let extent = self.view.image().extent();
+    [extent[0], extent[1]]
+  }
+
+  pub fn descriptor(&self, binding: u32) -> WriteDescriptorSet {
+    WriteDescriptorSet::image_view_sampler(binding, self.view.clone(), self.sampler.clone())
+  }
+
+  /// Uploads tightly packed sRGB RGBA pixels through a staging buffer and waits for the copy to finish.
+  pub fn from_rgba8(
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    extent: [u32; 2],
+    data: &[u8],
+    sampler: SamplerCreateInfo,
+  ) -> Resultat<Self> {
+    if rgba8_size(extent) != Some(data.len()) {
+      return Err(format!("{} bytes of pixels don't fit a {}x{} RGBA image", data.len(), extent[0], extent[1]).into());
+    }
+
+    let mut uploads = AutoCommandBufferBuilder::primary(
+      &command_buffer_allocator,
+      queue.queue_family_index(),
+      CommandBufferUsage::OneTimeSubmit,
+    )?;
+
+    let upload_buffer = Buffer::new_slice(
+      memory_allocator.clone(),
+      BufferCreateInfo {
+        usage: BufferUsage::TRANSFER_SRC,
+        ..Default::default()
+      },
+      AllocationCreateInfo {
+        memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+        ..Default::default()
+      },
+      data.len() as DeviceSize,
+    )?;
+
+    upload_buffer.write()?.copy_from_slice(data);
+
+    let image = Image::new(
+      memory_allocator,
+      ImageCreateInfo {
+        usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+        format: Format::R8G8B8A8_SRGB,
+        image_type: ImageType::Dim2d,
+        extent: [extent[0], extent[1], 1],
+        ..Default::default()
+      },
+      AllocationCreateInfo {
+        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+        ..Default::default()
+      },
+    )?;
+
+    uploads.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(upload_buffer, image.clone()))?;
+    uploads
+      .build()?
+      .execute(queue.clone())?
+      .then_signal_fence_and_flush()?
+      .wait(None)?;
+
+    Ok(Self {
+      view: ImageView::new_default(image)?,
+      sampler: Sampler::new(queue.device().clone(), sampler)?,
+    })
+  }
+
+  /// Names the image, view and sampler after `P`, see [`debug_name`].
+  pub fn name<P>(&self, purpose: &str) {
+    debug_name::<P>(&**self.view.image(), &format!("{purpose} image"));
+    debug_name::<P>(&*self.view, &format!("{purpose} view"));
+    debug_name::<P>(&*self.sampler, &format!("{purpose} sampler"));
+  }
+
+  pub fn from_png(
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    path: impl AsRef<Path>,
+    sampler: SamplerCreateInfo,
+  ) -> Resultat<Self> {
+    let path = path.as_ref();
+    let (extent, data) = decode_png(File::open(path)?).map_err(|e| format!("{}: {e}", path.display()))?;
+    Self::from_rgba8(queue, memory_allocator, command_buffer_allocator, extent, &data, sampler)
+  }
+
+  /// [`Texture::from_png`] using the engine's queue and allocators.
+  pub fn load_png(world: &World, path: impl AsRef<Path>, sampler: SamplerCreateInfo) -> Resultat<Self> {
+    Self::from_png(
+      world.resource::<ASingleton<Queue>>().clon(),
+      world.resource::<ASingleton<StandardMemoryAllocator>>().clon(),
+      world.resource::<ASingleton<StandardCommandBufferAllocator>>().clon(),
+      path,
+      sampler,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use png::BitDepth;
+
+  fn encode(
+    extent: [u32; 2],
+    configure: impl FnOnce(&mut png::Encoder<'static, &mut Vec<u8>>),
+    data: &[u8],
+  ) -> Vec<u8> {
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, extent[0], extent[1]);
+    configure(&mut encoder);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(data).unwrap();
+    writer.finish().unwrap();
+    png
+  }
+
+  fn decode(png: Vec<u8>) -> ([u32; 2], Vec<u8>) {
+    decode_png(png.as_slice()).unwrap()
+  }
+
+  fn color(color: ColorType, depth: BitDepth) -> impl FnOnce(&mut png::Encoder<'static, &mut Vec<u8>>) {
+    move |encoder| {
+      encoder.set_color(color);
+      encoder.set_depth(depth);
+    }
+  }
+
+  #[test]
+  fn expands_indexed() {
+    let palette = |encoder: &mut png::Encoder<'static, &mut Vec<u8>>| {
+      color(ColorType::Indexed, BitDepth::Eight)(encoder);
+      encoder.set_palette(vec![255, 0, 0, 0, 255, 0, 0, 0, 255]);
+    };
+    let png = encode([3, 1], palette, &[2, 0, 1]);
+    assert_eq!(decode(png), ([3, 1], vec![0, 0, 255, 255, 255, 0, 0, 255, 0, 255, 0, 255]));
+
+    let transparent = |encoder: &mut png::Encoder<'static, &mut Vec<u8>>| {
+      palette(encoder);
+      encoder.set_trns(vec![0, 128]);
+    };
+    let png = encode([3, 1], transparent, &[2, 0, 1]);
+    assert_eq!(decode(png), ([3, 1], vec![0, 0, 255, 255, 255, 0, 0, 0, 0, 255, 0, 128]));
+  }
+
+  #[test]
+  fn expands_low_bit_depth_indexed() {
+    let palette = |encoder: &mut png::Encoder<'static, &mut Vec<u8>>| {
+      color(ColorType::Indexed, BitDepth::Two)(encoder);
+      encoder.set_palette(vec![0, 0, 0, 10, 20, 30, 40, 50, 60]);
+    };
+    // Four 2-bit indices packed into one byte, the first one in the high bits
+    let png = encode([4, 1], palette, &[0b00_01_10_01]);
+    let (extent, rgba) = decode(png);
+    assert_eq!(extent, [4, 1]);
+    assert_eq!(rgba, [0, 0, 0, 255, 10, 20, 30, 255, 40, 50, 60, 255, 10, 20, 30, 255]);
+  }
+
+  #[test]
+  fn expands_gray() {
+    let png = encode([2, 1], color(ColorType::Grayscale, BitDepth::Eight), &[0, 200]);
+    assert_eq!(decode(png), ([2, 1], vec![0, 0, 0, 255, 200, 200, 200, 255]));
+    let png = encode([2, 1], color(ColorType::Grayscale, BitDepth::One), &[0b0100_0000]);
+    assert_eq!(decode(png), ([2, 1], vec![0, 0, 0, 255, 255, 255, 255, 255]));
+  }
+
+  #[test]
+  fn expands_gray_alpha() {
+    let png = encode([2, 1], color(ColorType::GrayscaleAlpha, BitDepth::Eight), &[10, 20, 30, 40]);
+    assert_eq!(decode(png), ([2, 1], vec![10, 10, 10, 20, 30, 30, 30, 40]));
+  }
+
+  #[test]
+  fn expands_rgb() {
+    let png = encode([1, 2], color(ColorType::Rgb, BitDepth::Eight), &[1, 2, 3, 4, 5, 6]);
+    assert_eq!(decode(png), ([1, 2], vec![1, 2, 3, 255, 4, 5, 6, 255]));
+  }
+
+  #[test]
+  fn keeps_rgba() {
+    let png = encode([1, 1], color(ColorType::Rgba, BitDepth::Eight), &[1, 2, 3, 4]);
+    assert_eq!(decode(png), ([1, 1], vec![1, 2, 3, 4]));
+  }
+
+  #[test]
+  fn strips_16_bit_channels_to_their_high_byte() {
+    let data = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xff, 0xff];
+    let png = encode([1, 1], color(ColorType::Rgba, BitDepth::Sixteen), &data);
+    assert_eq!(decode(png), ([1, 1], vec![0x12, 0x56, 0x9a, 0xff]));
+    let png = encode([1, 1], color(ColorType::Rgb, BitDepth::Sixteen), &data[..6]);
+    assert_eq!(decode(png), ([1, 1], vec![0x12, 0x56, 0x9a, 0xff]));
+    let png = encode([1, 1], color(ColorType::Grayscale, BitDepth::Sixteen), &data[..2]);
+    assert_eq!(decode(png), ([1, 1], vec![0x12, 0x12, 0x12, 0xff]));
+  }
+
+  #[test]
+  fn rejects_garbage() {
+    assert!(decode_png(&b"not a png"[..]).is_err());
+  }
+
+  #[test]
+  fn rgba8_size_does_not_overflow() {
+    assert_eq!(rgba8_size([3, 2]), Some(24));
+    assert_eq!(rgba8_size([0, 100]), Some(0));
+    assert_eq!(rgba8_size([u32::MAX, u32::MAX]), None);
+  }
+}
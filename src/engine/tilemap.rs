@@ -1,15 +1,137 @@
-pub struct Tile {}
+use bevy_ecs::prelude::*;
 
+/// A single cell of a [`Tilemap`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tile {
+  /// Index into the atlas, counted row by row starting from the top left tile.
+  pub atlas_index: u32,
+  /// Any combination of [`Tile::FLIP_X`], [`Tile::FLIP_Y`] and [`Tile::ROTATE_90`].
+  pub transform: u8,
+  /// Free for gameplay code (collision, spawn markers, ...), ignored by rendering.
+  pub user_flags: u16,
+}
+
+impl Tile {
+  pub const FLIP_X: u8 = 1 << 0;
+  pub const FLIP_Y: u8 = 1 << 1;
+  /// Rotates the tile 90 degrees clockwise. Applied before flipping, so together they cover all 8 orientations.
+  pub const ROTATE_90: u8 = 1 << 2;
+
+  pub fn new(atlas_index: u32) -> Self {
+    Self {
+      atlas_index,
+      ..Default::default()
+    }
+  }
+
+  pub fn with_transform(self, transform: u8) -> Self {
+    Self { transform, ..self }
+  }
+
+  pub fn with_user_flags(self, user_flags: u16) -> Self {
+    Self { user_flags, ..self }
+  }
+}
+
+/// A grid of [`Tile`]s addressed with `(x, y)` coordinates, `(0, 0)` being the first tile of the first row.
+#[derive(Component, Clone, Debug)]
 pub struct Tilemap {
-  pub tiles: Vec<Tile>,
-  pub size: (usize, usize),
+  tiles: Vec<Tile>,
+  size: (usize, usize),
 }
 
 impl Tilemap {
   pub fn new(size: (usize, usize)) -> Self {
+    Self::filled(size, Tile::default())
+  }
+
+  pub fn filled(size: (usize, usize), tile: Tile) -> Self {
     Self {
-      tiles: (0..size.0 * size.1).map(|_x| Tile {}).collect(),
+      tiles: vec![tile; size.0 * size.1],
       size,
     }
   }
+
+  pub fn size(&self) -> (usize, usize) {
+    self.size
+  }
+
+  pub fn in_bounds(&self, (x, y): (usize, usize)) -> bool {
+    x < self.size.0 && y < self.size.1
+  }
+
+  fn index(&self, pos: (usize, usize)) -> Option<usize> {
+    self.in_bounds(pos).then(|| pos.1 * self.size.0 + pos.0)
+  }
+
+  pub fn get(&self, pos: (usize, usize)) -> Option<&Tile> {
+    self.index(pos).map(|i| &self.tiles[i])
+  }
+
+  pub fn get_mut(&mut self, pos: (usize, usize)) -> Option<&mut Tile> {
+    self.index(pos).map(|i| &mut self.tiles[i])
+  }
+
+  /// Replaces the tile at `pos` and returns the previous one, or `None` if `pos` is outside the map.
+  pub fn set(&mut self, pos: (usize, usize), tile: Tile) -> Option<Tile> {
+    self.get_mut(pos).map(|old| std::mem::replace(old, tile))
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), &Tile)> {
+    let width = self.size.0;
+    self.tiles.iter().enumerate().map(move |(i, tile)| ((i % width, i / width), tile))
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = ((usize, usize), &mut Tile)> {
+    let width = self.size.0;
+    self.tiles.iter_mut().enumerate().map(move |(i, tile)| ((i % width, i / width), tile))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn in_bounds_at_the_edges() {
+    let map = Tilemap::new((3, 2));
+    assert!(map.in_bounds((0, 0)));
+    assert!(map.in_bounds((2, 1)));
+    assert!(!map.in_bounds((3, 1)));
+    assert!(!map.in_bounds((2, 2)));
+    assert!(!map.in_bounds((usize::MAX, 0)));
+    assert!(!Tilemap::new((0, 0)).in_bounds((0, 0)));
+  }
+
+  #[test]
+  fn out_of_range_is_none() {
+    let mut map = Tilemap::new((3, 2));
+    assert!(map.get((3, 0)).is_none());
+    assert!(map.get((0, 2)).is_none());
+    assert!(map.get_mut((3, 0)).is_none());
+    assert_eq!(map.set((0, 2), Tile::new(1)), None);
+    assert!(map.iter().all(|(_, tile)| *tile == Tile::default()));
+  }
+
+  #[test]
+  fn set_returns_the_previous_tile() {
+    let mut map = Tilemap::filled((3, 2), Tile::new(7));
+    let tile = Tile::new(1).with_transform(Tile::FLIP_X).with_user_flags(4);
+    assert_eq!(map.set((2, 1), tile), Some(Tile::new(7)));
+    assert_eq!(map.set((2, 1), Tile::new(2)), Some(tile));
+    assert_eq!(map.get((2, 1)), Some(&Tile::new(2)));
+    assert_eq!(map.get((1, 2)), None);
+  }
+
+  #[test]
+  fn iterates_row_by_row() {
+    let mut map = Tilemap::new((3, 2));
+    for ((x, y), tile) in map.iter_mut() {
+      *tile = Tile::new((y * 10 + x) as u32);
+    }
+    let positions = map.iter().map(|(pos, _)| pos).collect::<Vec<_>>();
+    assert_eq!(positions, [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+    assert!(map.iter().all(|((x, y), tile)| map.get((x, y)) == Some(tile)));
+    assert_eq!(map.get((2, 1)).map(|tile| tile.atlas_index), Some(12));
+  }
 }
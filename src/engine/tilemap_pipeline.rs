@@ -0,0 +1,273 @@
+use crate::engine::camera::Camera2d;
+use crate::engine::game_target::GameTarget;
+use crate::engine::internals::DeviceInitializers;
+use crate::engine::render_graph::RenderNode;
+use crate::engine::render_pipeline::{RenderPipeline, RenderPipelinePlugin};
+use crate::engine::texture::Texture;
+use crate::engine::tilemap::Tilemap;
+use crate::engine::{debug_name, handle_result, ASingleton, AssociatedResource, Resultat};
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemId;
+use smallvec::smallvec;
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::{Device, Queue};
+use vulkano::image::sampler::{Filter, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexBufferDescription};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::shader::EntryPoint;
+
+const ATLAS_PATH: &str = "assets/tiles.png";
+/// Size in pixels of a single tile in the atlas, and so in world units.
+const ATLAS_TILE_SIZE: u32 = 16;
+
+/// Draws every [`Tilemap`] entity into the [`GameTarget`] with one instanced call per map and [`Camera2d`], sampling
+/// tiles from `assets/tiles.png`. Maps start at the world origin.
+pub struct TilemapPipeline;
+
+#[derive(BufferContents, Vertex, Clone, Copy)]
+#[repr(C)]
+pub struct TileInstance {
+  #[format(R32G32_SFLOAT)]
+  in_coord: [f32; 2],
+  #[format(R32G32_UINT)]
+  tile: [u32; 2],
+  #[format(R32_UINT)]
+  transform: u32,
+}
+
+mod vs {
+  vulkano_shaders::shader! {
+      ty: "vertex",
+      path: "assets/shaders/vertex.glsl",
+      include: ["assets/shaders/lib"]
+  }
+}
+
+mod fs {
+  vulkano_shaders::shader! {
+      ty: "fragment",
+      path: "assets/shaders/fragment.glsl",
+      include: ["assets/shaders/lib"]
+  }
+}
+
+impl RenderPipeline for TilemapPipeline {
+  const NAME: &'static str = "tilemap";
+  const VERTEX_SHADER: &'static str = "assets/shaders/vertex.glsl";
+  const FRAGMENT_SHADER: &'static str = "assets/shaders/fragment.glsl";
+
+  fn shaders(device: Arc<Device>) -> Resultat<(EntryPoint, EntryPoint)> {
+    let vs = vs::load(device.clone())?.entry_point("main").unwrap();
+    let fs = fs::load(device)?.entry_point("main").unwrap();
+    Ok((vs, fs))
+  }
+
+  fn vertex_buffer() -> VertexBufferDescription {
+    TileInstance::per_instance()
+  }
+
+  fn input_assembly() -> InputAssemblyState {
+    InputAssemblyState {
+      topology: PrimitiveTopology::TriangleStrip,
+      ..Default::default()
+    }
+  }
+
+  fn bind_system(world: &mut World) -> SystemId {
+    world.register_system(TilemapPipeline::bind.pipe(handle_result))
+  }
+
+  fn node(node: RenderNode) -> RenderNode {
+    node.writes("Game")
+  }
+}
+
+impl TilemapPipeline {
+  fn atlas(
+    queue: Arc<Queue>,
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+  ) -> Resultat<(Arc<PersistentDescriptorSet>, [u32; 2])> {
+    // Nearest filtering keeps the pixel art crisp
+    let texture = Texture::from_png(
+      queue,
+      memory_allocator,
+      command_buffer_allocator,
+      ATLAS_PATH,
+      SamplerCreateInfo {
+        mag_filter: Filter::Nearest,
+        min_filter: Filter::Nearest,
+        address_mode: [SamplerAddressMode::ClampToEdge; 3],
+        ..Default::default()
+      },
+    )?;
+    texture.name::<Self>("atlas");
+
+    let layout = pipeline.layout().set_layouts().get(0).unwrap();
+    let set = PersistentDescriptorSet::new(&descriptor_set_allocator, layout.clone(), [texture.descriptor(0)], [])?;
+    debug_name::<Self>(&*set, "atlas descriptor set");
+
+    Ok((set, Self::atlas_size(texture.extent())?))
+  }
+
+  /// Tiles across and down an atlas of `extent` pixels, which has to be a whole number of tiles.
+  fn atlas_size(extent: [u32; 2]) -> Resultat<[u32; 2]> {
+    let [width, height] = extent;
+    if width == 0 || height == 0 || width % ATLAS_TILE_SIZE != 0 || height % ATLAS_TILE_SIZE != 0 {
+      let tile = ATLAS_TILE_SIZE;
+      return Err(format!("a {width}x{height} atlas isn't made of whole {tile}x{tile} tiles").into());
+    }
+    Ok([width / ATLAS_TILE_SIZE, height / ATLAS_TILE_SIZE])
+  }
+
+  /// Loads the atlas for the pipeline built just before.
+  fn init(world: &mut World) -> Resultat<()> {
+    let memory_allocator = world.resource::<ASingleton<StandardMemoryAllocator>>();
+    let pipeline = world.resource::<AssociatedResource<Self, Arc<GraphicsPipeline>>>();
+    let queue = world.resource::<ASingleton<Queue>>();
+    let descriptor_set_allocator = world.resource::<ASingleton<StandardDescriptorSetAllocator>>();
+    let command_buffer_allocator = world.resource::<ASingleton<StandardCommandBufferAllocator>>();
+
+    let (descriptor_set, atlas_size) = Self::atlas(
+      queue.clon(),
+      (**pipeline).clone(),
+      memory_allocator.clon(),
+      command_buffer_allocator.clon(),
+      descriptor_set_allocator.clon(),
+    )?;
+
+    world.insert_resource(AssociatedResource::<Self, _>::new(descriptor_set));
+    world.insert_resource(AssociatedResource::<Self, [u32; 2]>::new(atlas_size));
+    world.insert_resource(AssociatedResource::<Self, Vec<Subbuffer<[TileInstance]>>>::new(vec![]));
+    Ok(())
+  }
+}
+
+impl TilemapPipeline {
+  fn update(
+    memory_allocator: Res<ASingleton<StandardMemoryAllocator>>,
+    atlas_size: Res<AssociatedResource<Self, [u32; 2]>>,
+    tilemaps: Query<Ref<Tilemap>>,
+    mut removed: RemovedComponents<Tilemap>,
+    mut instances: ResMut<AssociatedResource<Self, Vec<Subbuffer<[TileInstance]>>>>,
+  ) -> Resultat<()> {
+    let removed = removed.read().count() > 0;
+    // Only `init` changes the instances outside this system, after which every map needs uploading again
+    if !removed && !instances.is_changed() && !tilemaps.iter().any(|tilemap| tilemap.is_changed()) {
+      return Ok(());
+    }
+
+    instances.clear();
+    for tilemap in &tilemaps {
+      if tilemap.size().0 * tilemap.size().1 == 0 {
+        continue;
+      }
+      let tiles = tilemap.iter().map(|((x, y), tile)| TileInstance {
+        in_coord: [(x as u32 * ATLAS_TILE_SIZE) as f32, (y as u32 * ATLAS_TILE_SIZE) as f32],
+        tile: [tile.atlas_index % atlas_size[0], tile.atlas_index / atlas_size[0]],
+        transform: tile.transform as u32,
+      });
+      let instance_buffer = Buffer::from_iter(
+        memory_allocator.clon(),
+        BufferCreateInfo {
+          usage: BufferUsage::VERTEX_BUFFER,
+          ..Default::default()
+        },
+        AllocationCreateInfo {
+          memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+          ..Default::default()
+        },
+        tiles,
+      )?;
+      debug_name::<Self>(&**instance_buffer.buffer(), "instances");
+      instances.push(instance_buffer);
+    }
+    Ok(())
+  }
+
+  fn bind(
+    mut builder: NonSendMut<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>,
+    pipeline: Res<AssociatedResource<Self, Arc<GraphicsPipeline>>>,
+    descriptor_set: Res<AssociatedResource<Self, Arc<PersistentDescriptorSet>>>,
+    atlas_size: Res<AssociatedResource<Self, [u32; 2]>>,
+    instances: Res<AssociatedResource<Self, Vec<Subbuffer<[TileInstance]>>>>,
+    game_target: Res<GameTarget>,
+    cameras: Query<&Camera2d>,
+  ) -> Resultat<()> {
+    if instances.is_empty() {
+      return Ok(());
+    }
+    let mut cameras = cameras.iter().copied().collect::<Vec<_>>();
+    if cameras.is_empty() {
+      cameras.push(Camera2d::default());
+    }
+    let extent = game_target.extent();
+    builder.bind_pipeline_graphics(pipeline.clone())?.bind_descriptor_sets(
+      PipelineBindPoint::Graphics,
+      pipeline.layout().clone(),
+      0,
+      descriptor_set.clone(),
+    )?;
+    for camera in cameras {
+      builder
+        .set_viewport(0, smallvec![camera.viewport(extent)])?
+        .push_constants(
+          pipeline.layout().clone(),
+          0,
+          vs::Constants {
+            view_projection: camera.view_projection(extent),
+            atlas_size: **atlas_size,
+            tile_size: ATLAS_TILE_SIZE as f32,
+          },
+        )?;
+      for tilemap_instances in instances.iter() {
+        builder
+          .bind_vertex_buffers(0, tilemap_instances.clone())?
+          .draw(4, tilemap_instances.len() as u32, 0, 0)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Plugin for TilemapPipeline {
+  fn build(&self, app: &mut App) {
+    app.add_plugins(RenderPipelinePlugin::<Self>::default());
+    app.world.resource_mut::<DeviceInitializers>().register(TilemapPipeline::init);
+    app.add_systems(PostUpdate, TilemapPipeline::update.pipe(handle_result));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_atlas_tiles() {
+    assert_eq!(TilemapPipeline::atlas_size([16, 16]).unwrap(), [1, 1]);
+    assert_eq!(TilemapPipeline::atlas_size([256, 48]).unwrap(), [16, 3]);
+  }
+
+  #[test]
+  fn rejects_atlases_smaller_than_a_tile() {
+    assert!(TilemapPipeline::atlas_size([8, 16]).is_err());
+    assert!(TilemapPipeline::atlas_size([16, 15]).is_err());
+    assert!(TilemapPipeline::atlas_size([0, 0]).is_err());
+  }
+
+  #[test]
+  fn rejects_partial_tiles() {
+    assert!(TilemapPipeline::atlas_size([40, 32]).is_err());
+    assert!(TilemapPipeline::atlas_size([32, 17]).is_err());
+  }
+}
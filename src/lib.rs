@@ -29,7 +29,7 @@ pub struct Ruminative;
 impl PluginGroup for Ruminative {
   fn build(self) -> PluginGroupBuilder {
     PluginGroupBuilder::start::<Self>()
-      .add(RuminativeEnginePlugin)
+      .add(RuminativeEnginePlugin::default())
       .add(RuminativeEditorPlugin)
   }
 }
\ No newline at end of file